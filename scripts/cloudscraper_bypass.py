@@ -0,0 +1,48 @@
+#!/usr/bin/env python3
+"""Requests pages for `hdpc-dl --fetcher command` with cloudscraper.
+
+Usage: hdpc-dl --fetcher command --bypass-command "python3 scripts/cloudscraper_bypass.py" ...
+
+Reads one JSON request per line from stdin ({"url": "...", "proxy": "..."}, where the proxy is optional)
+and writes one JSON response per line to stdout. SOCKS proxies need `pip install requests[socks]`.
+"""
+
+import json
+import sys
+
+import cloudscraper
+
+
+def main():
+    scraper = cloudscraper.create_scraper()
+
+    for line in sys.stdin:
+        request = json.loads(line)
+
+        try:
+            proxy = request.get("proxy")
+            proxies = {"http": proxy, "https": proxy} if proxy else None
+            res = scraper.get(request["url"], proxies=proxies)
+            response = {
+                "text": res.text,
+                "status": res.status_code,
+                "cookies": [
+                    {
+                        "name": cookie.name,
+                        "value": cookie.value or "",
+                        "domain": cookie.domain,
+                        "path": cookie.path,
+                    }
+                    for cookie in scraper.cookies
+                ],
+                "user_agent": scraper.headers.get("User-Agent"),
+            }
+        except Exception as e:
+            response = {"error": str(e)}
+
+        sys.stdout.write(json.dumps(response) + "\n")
+        sys.stdout.flush()
+
+
+if __name__ == "__main__":
+    main()
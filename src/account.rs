@@ -0,0 +1,194 @@
+use std::{fs, io::Write, path::Path};
+
+use anyhow::Context;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::{cookie::CookieStore, Url};
+use serde::Serialize;
+
+use crate::{config::Secret, constants, fetcher::Fetcher, http::HttpSettings};
+
+lazy_static! {
+    static ref LOGGED_IN_RX: Regex = Regex::new(r#"<body[^>]*class="[^"]*\blogged-in\b"#).unwrap();
+}
+
+/// The prefix of the cookie WordPress sets for logged in users
+const LOGGED_IN_COOKIE_PREFIX: &str = "wordpress_logged_in_";
+
+/// How to log in to the site
+pub enum Login {
+    Credentials {
+        username: String,
+        password: Secret,
+    },
+
+    /// A `name=value` cookie copied from a browser which is logged in
+    SessionCookie(String),
+}
+
+/// A cookie of the session file, in the JSON cookie format `--cookies` reads
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredCookie<'a> {
+    name: &'a str,
+    value: &'a str,
+    domain: &'a str,
+    path: &'a str,
+    host_only: bool,
+    secure: bool,
+}
+
+/// Whether a page was shown to a logged in user
+pub fn is_logged_in(html: &str) -> bool {
+    LOGGED_IN_RX.is_match(html)
+}
+
+/// Writes the `name=value` pairs of `cookies` to the session file, which only the current user may read
+fn write_session(path: &Path, cookies: &str) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create the data folder")?;
+    }
+
+    let domain = Url::parse(constants::SEARCH_PATH)?
+        .host_str()
+        .context("The site has no host")?
+        .to_string();
+
+    let cookies: Vec<_> = cookies
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .map(|(name, value)| StoredCookie {
+            name,
+            value,
+            domain: &domain,
+            path: "/",
+            host_only: false,
+            secure: true,
+        })
+        .collect();
+
+    let write = || {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+
+        // The mode only applies to new files, so an existing session may still be readable by others
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+
+        file.write_all(&serde_json::to_vec_pretty(&cookies)?)
+    };
+
+    write().with_context(|| {
+        format!(
+            "Failed to write the session to \"{}\"",
+            path.to_string_lossy()
+        )
+    })
+}
+
+/// Logs in and saves the session cookies to `session_path`, where later runs load them from
+pub async fn login(
+    settings: &HttpSettings,
+    session_path: &Path,
+    login: Login,
+) -> anyhow::Result<()> {
+    let site = Url::parse(constants::SEARCH_PATH)?;
+
+    let cookies = match login {
+        Login::Credentials { username, password } => {
+            let jar = settings.cookie_jar()?;
+            let client = settings.client_builder(jar.clone())?.build()?;
+
+            // WordPress refuses logins without its test cookie
+            client
+                .get(constants::LOGIN_PATH)
+                .send()
+                .await?
+                .error_for_status()?;
+            client
+                .post(constants::LOGIN_PATH)
+                .form(&[
+                    ("log", username.as_str()),
+                    ("pwd", password.expose()),
+                    ("rememberme", "forever"),
+                    ("testcookie", "1"),
+                    ("redirect_to", constants::SEARCH_PATH),
+                ])
+                .send()
+                .await?
+                .error_for_status()?;
+
+            let cookies = jar
+                .cookies(&site)
+                .and_then(|cookies| cookies.to_str().ok().map(str::to_string))
+                .unwrap_or_default();
+
+            if !cookies.contains(LOGGED_IN_COOKIE_PREFIX) {
+                anyhow::bail!("Logging in as {username} failed; check the username and password");
+            }
+
+            cookies
+        }
+        Login::SessionCookie(cookie) => {
+            if !cookie.contains('=') {
+                anyhow::bail!("Expected a cookie like \"{LOGGED_IN_COOKIE_PREFIX}...=...\"");
+            }
+
+            cookie
+        }
+    };
+
+    write_session(session_path, &cookies)?;
+    println!(
+        "Saved the session to \"{}\"; check it with `account status`",
+        session_path.to_string_lossy()
+    );
+
+    Ok(())
+}
+
+/// Deletes the saved session
+pub fn logout(session_path: &Path) -> anyhow::Result<()> {
+    if !session_path.exists() {
+        println!("Not logged in.");
+        return Ok(());
+    }
+
+    fs::remove_file(session_path).with_context(|| {
+        format!(
+            "Failed to delete the session \"{}\"",
+            session_path.to_string_lossy()
+        )
+    })?;
+    println!("Deleted the session.");
+
+    Ok(())
+}
+
+/// Fails unless the site shows `url` to a logged in user, so a lost session doesn't crawl the logged out page
+pub async fn ensure_logged_in(fetcher: &dyn Fetcher, url: &str) -> anyhow::Result<()> {
+    if !is_logged_in(&fetcher.get_page(url).await?) {
+        anyhow::bail!(
+            "The site shows {url} to a logged out user; log in again with `account login`. \
+            The bypass fetcher doesn't send the session, so use --fetcher plain or command"
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks whether the site sees the requests as logged in
+pub async fn status(fetcher: &dyn Fetcher) -> anyhow::Result<()> {
+    let text = fetcher.get_page(constants::SEARCH_PATH).await?;
+
+    if is_logged_in(&text) {
+        println!("Logged in.");
+    } else {
+        println!("Not logged in; log in with `account login` or pass the cookies of a browser with --cookies.");
+    }
+
+    Ok(())
+}
@@ -1,24 +1,95 @@
-use pyo3::prelude::*;
+use std::sync::Mutex;
+
+use pyo3::{prelude::*, types::PyDict};
 use tracing::info;
 
-/// Use a Python module to bypass some crawling annoyances
-pub fn http_get_bypassed(url: impl AsRef<str>) -> Result<String, anyhow::Error> {
-    info!(
-        "Downloading HTML with Python from {url}",
-        url = url.as_ref()
-    );
+use crate::fetcher::SessionCookie;
+
+/// A page requested with cloudscraper, with all cookies of the session afterwards
+#[derive(Debug)]
+pub struct ScrapedPage {
+    pub text: String,
+    pub cookies: Vec<SessionCookie>,
+}
+
+/// A cloudscraper session, which is kept for the whole run so its challenges are only solved once
+///
+/// Requests block, so call [`Scraper::get`] on a blocking thread.
+pub struct Scraper {
+    // Requests sessions aren't meant to be shared between threads, and the GIL is released while waiting
+    session: Mutex<Py<PyAny>>,
+    user_agent: String,
+}
+
+impl Scraper {
+    /// Starts a session, which sends `user_agent` instead of the one cloudscraper picks if it's set
+    pub fn new(user_agent: Option<&str>) -> anyhow::Result<Self> {
+        pyo3::prepare_freethreaded_python();
+        info!("Prepared the Python FFI");
+
+        Python::with_gil(|py| {
+            let session: Py<PyAny> = PyModule::import(py, "cloudscraper")?
+                .call_method0("create_scraper")?
+                .into();
+
+            let headers = session.as_ref(py).getattr("headers")?;
+            if let Some(user_agent) = user_agent {
+                headers.set_item("User-Agent", user_agent)?;
+            }
+
+            // The clearance cookie only works with the user agent it was issued to
+            let user_agent = headers.get_item("User-Agent")?.extract()?;
+
+            Ok(Self {
+                session: Mutex::new(session),
+                user_agent,
+            })
+        })
+    }
+
+    /// The user agent every request of the session is sent with
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    /// Requests a page through `proxy`, if it's set, solving a challenge if the site shows one
+    pub fn get(&self, url: &str, proxy: Option<&str>) -> anyhow::Result<ScrapedPage> {
+        info!("Downloading HTML with Python from {url}");
+
+        let session = self
+            .session
+            .lock()
+            .map_err(|_| anyhow::anyhow!("A previous cloudscraper request panicked"))?;
+
+        Python::with_gil(|py| {
+            let session = session.as_ref(py);
 
-    let url = url.as_ref();
+            let kwargs = PyDict::new(py);
+            if let Some(proxy) = proxy {
+                let proxies = PyDict::new(py);
+                proxies.set_item("http", proxy)?;
+                proxies.set_item("https", proxy)?;
+                kwargs.set_item("proxies", proxies)?;
+            }
 
-    Python::with_gil(|py| -> anyhow::Result<String> {
-        let scraper: Py<PyAny> = PyModule::import(py, "cloudscraper")?
-            .call_method0("create_scraper")?
-            .into();
+            let res = session.call_method("get", (url,), Some(kwargs))?;
+            res.call_method0("raise_for_status")?;
+            let text = res.getattr("text")?.extract()?;
 
-        let res = scraper.call_method1(py, "get", (url,))?;
+            let mut cookies = vec![];
+            for cookie in session.getattr("cookies")?.iter()? {
+                let cookie = cookie?;
+                let value: Option<String> = cookie.getattr("value")?.extract()?;
 
-        let text: String = res.getattr(py, "text")?.extract(py)?;
+                cookies.push(SessionCookie {
+                    name: cookie.getattr("name")?.extract()?,
+                    value: value.unwrap_or_default(),
+                    domain: cookie.getattr("domain")?.extract()?,
+                    path: cookie.getattr("path")?.extract()?,
+                });
+            }
 
-        Ok(text)
-    })
+            Ok(ScrapedPage { text, cookies })
+        })
+    }
 }
@@ -0,0 +1,330 @@
+use std::{
+    process::Stdio,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::{cookie::Jar, Client, Url};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+use crate::{
+    fetcher::{add_session_cookies, Fetcher, SessionCookie},
+    http::HttpSettings,
+};
+
+/// A line written to the stdin of the command
+#[derive(Debug, Serialize)]
+struct CommandRequest<'a> {
+    url: &'a str,
+
+    /// The proxy to request the page through, if one is set for it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy: Option<&'a str>,
+}
+
+/// A line read from the stdout of the command
+#[derive(Debug, Deserialize)]
+struct CommandResponse {
+    /// The HTML of the page
+    text: Option<String>,
+
+    /// The HTTP status of the page; error statuses fail the request
+    status: Option<u16>,
+
+    /// Why the page couldn't be requested
+    error: Option<String>,
+
+    /// All cookies of the session of the command, which are passed on to the client
+    #[serde(default)]
+    cookies: Vec<SessionCookie>,
+
+    /// The user agent the cookies were issued to
+    user_agent: Option<String>,
+}
+
+/// The running command
+struct Process {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+
+    /// Whether a request was written without its response being read, e.g. because the request was
+    /// cancelled, so the next response can't be trusted to belong to the next request
+    in_exchange: bool,
+
+    // The command is killed when this is dropped
+    _child: Child,
+}
+
+impl Process {
+    /// Starts `command` with the shell of the platform
+    fn start(command: &str) -> anyhow::Result<Self> {
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+
+        let mut child = shell
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start the bypass command {command}"))?;
+
+        Ok(Self {
+            stdin: child
+                .stdin
+                .take()
+                .context("The bypass command has no stdin")?,
+            stdout: BufReader::new(
+                child
+                    .stdout
+                    .take()
+                    .context("The bypass command has no stdout")?,
+            ),
+            in_exchange: false,
+            _child: child,
+        })
+    }
+}
+
+/// Requests pages through an external command, e.g. a script using `cloudscraper` or another solver
+///
+/// The command runs for the whole run and gets one JSON request per line on stdin,
+/// `{"url": "..."}`, with a `"proxy"` URL if the page should go through one,
+/// and answers each with one JSON line on stdout:
+/// `{"text": "<html>", "status": 200, "cookies": [{"name": "...", "value": "...", "domain": "...", "path": "/"}], "user_agent": "..."}`,
+/// or `{"error": "..."}`. Everything but `text` or `error` is optional.
+pub struct CommandFetcher {
+    command: String,
+    process: Mutex<Process>,
+    settings: HttpSettings,
+    jar: Arc<Jar>,
+
+    /// The client for requests before the command sent a user agent
+    client: Client,
+
+    /// The client with the user agent of the first response which had one, and that user agent
+    command_client: OnceLock<(String, Client)>,
+}
+
+impl CommandFetcher {
+    /// Starts `command` with the shell of the platform
+    pub fn spawn(command: &str, settings: HttpSettings, jar: Arc<Jar>) -> anyhow::Result<Self> {
+        info!("Starting the bypass command {command}");
+
+        let client = settings.client_builder(jar.clone())?.build()?;
+
+        Ok(Self {
+            command: command.to_string(),
+            process: Mutex::new(Process::start(command)?),
+            settings,
+            jar,
+            client,
+            command_client: OnceLock::new(),
+        })
+    }
+
+    /// Sends one request and waits for its response, so requests never interleave
+    ///
+    /// If an earlier request was cancelled halfway, the command is restarted first, as its pipes may hold
+    /// a partial request or a response nobody waits for anymore.
+    async fn request(&self, url: &str) -> anyhow::Result<CommandResponse> {
+        let mut process = self.process.lock().await;
+
+        if process.in_exchange {
+            warn!(
+                "Restarting the bypass command {}, as a request to it was cancelled",
+                self.command
+            );
+            *process = Process::start(&self.command)?;
+        }
+
+        let proxy = self.settings.proxies.select(&Url::parse(url)?);
+        let mut line = serde_json::to_string(&CommandRequest {
+            url,
+            proxy: proxy.as_ref().map(Url::as_str),
+        })?;
+        line.push('\n');
+        process.in_exchange = true;
+        let write = async {
+            process.stdin.write_all(line.as_bytes()).await?;
+            process.stdin.flush().await
+        };
+        write
+            .await
+            .with_context(|| format!("The bypass command {} stopped", self.command))?;
+
+        let mut line = String::new();
+        if process.stdout.read_line(&mut line).await? == 0 {
+            anyhow::bail!("The bypass command {} exited", self.command);
+        }
+        process.in_exchange = false;
+
+        serde_json::from_str(&line).with_context(|| {
+            format!(
+                "Unexpected response of the bypass command {}: {}",
+                self.command,
+                line.trim_end()
+            )
+        })
+    }
+}
+
+#[async_trait]
+impl Fetcher for CommandFetcher {
+    async fn get_page(&self, url: &str) -> anyhow::Result<String> {
+        info!("Downloading HTML with the bypass command from {url}");
+
+        let res = self.request(url).await?;
+
+        if let Some(error) = res.error {
+            anyhow::bail!("The bypass command couldn't get {url}: {error}");
+        }
+        if let Some(status) = res.status.filter(|status| *status >= 400) {
+            anyhow::bail!("The bypass command got HTTP status {status} for {url}");
+        }
+
+        add_session_cookies(&self.jar, url, res.cookies)?;
+
+        // Only the first user agent can be used, as clients handed out can't be changed anymore,
+        // and one set by the user always wins
+        if let Some(user_agent) = res
+            .user_agent
+            .filter(|_| self.settings.user_agent.is_none())
+        {
+            match self.command_client.get() {
+                None => {
+                    let client = self
+                        .settings
+                        .client_builder(self.jar.clone())?
+                        .user_agent(&user_agent)
+                        .build()?;
+                    let _ = self.command_client.set((user_agent, client));
+                }
+                Some((first, _)) if *first != user_agent => warn!(
+                    "The bypass command switched its user agent to {user_agent}, but API and file requests keep using {first}"
+                ),
+                Some(_) => {}
+            }
+        }
+
+        res.text
+            .with_context(|| format!("The bypass command sent no text for {url}"))
+    }
+
+    fn client(&self) -> &Client {
+        self.command_client
+            .get()
+            .map_or(&self.client, |(_, client)| client)
+    }
+}
+
+// The stub command is a POSIX shell script
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_utils::temp_dir;
+
+    /// Starts a stub command answering by the path of the URL, which notes each start in `starts`
+    fn stub_command(name: &str) -> (CommandFetcher, std::path::PathBuf) {
+        let dir = temp_dir(name);
+        let starts = dir.join("starts");
+        let script = dir.join("stub.sh");
+        std::fs::write(
+            &script,
+            r#"echo start >> "$1"
+while read -r line; do
+  case "$line" in
+    *'/ok"'*) echo '{"text": "<html>ok</html>", "status": 200}' ;;
+    *'/error"'*) echo '{"error": "Challenge not solved"}' ;;
+    *'/forbidden"'*) echo '{"text": "", "status": 403}' ;;
+    *'/slow"'*) sleep 5; echo '{"text": "<html>slow</html>"}' ;;
+    *'/exit"'*) exit 0 ;;
+  esac
+done
+"#,
+        )
+        .unwrap();
+
+        let command = format!("sh {} {}", script.display(), starts.display());
+        let fetcher =
+            CommandFetcher::spawn(&command, HttpSettings::default(), Arc::new(Jar::default()))
+                .unwrap();
+
+        (fetcher, starts)
+    }
+
+    fn start_count(starts: &std::path::Path) -> usize {
+        std::fs::read_to_string(starts).unwrap().lines().count()
+    }
+
+    #[tokio::test]
+    async fn passes_on_responses_and_failures() {
+        let (fetcher, starts) = stub_command("command-responses");
+
+        assert_eq!(
+            fetcher.get_page("https://example.com/ok").await.unwrap(),
+            "<html>ok</html>"
+        );
+
+        let error = fetcher
+            .get_page("https://example.com/error")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Challenge not solved"));
+
+        let error = fetcher
+            .get_page("https://example.com/forbidden")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("HTTP status 403"));
+
+        let error = fetcher
+            .get_page("https://example.com/exit")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("exited"));
+
+        // The unanswered request restarts the command
+        assert_eq!(
+            fetcher.get_page("https://example.com/ok").await.unwrap(),
+            "<html>ok</html>"
+        );
+        assert_eq!(start_count(&starts), 2);
+    }
+
+    #[tokio::test]
+    async fn restarts_after_a_cancelled_request() {
+        let (fetcher, starts) = stub_command("command-cancelled");
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(200),
+            fetcher.get_page("https://example.com/slow"),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        // Without the restart, this would read the response to the slow request
+        assert_eq!(
+            fetcher.get_page("https://example.com/ok").await.unwrap(),
+            "<html>ok</html>"
+        );
+        assert_eq!(start_count(&starts), 2);
+    }
+}
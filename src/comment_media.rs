@@ -0,0 +1,285 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Context;
+use html_escape::decode_html_entities;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use reqwest::Url;
+use tracing::warn;
+
+use crate::{
+    comments::ThreadedComment,
+    constants,
+    fetcher::{url_hash, Fetcher},
+};
+
+lazy_static! {
+    static ref MEDIA_ATTRIBUTE_RX: Regex =
+        Regex::new(r#"(?i)\b(src|href)\s*=\s*"([^"]+)""#).unwrap();
+}
+
+/// The extensions of linked files which are archived, as opposed to links to pages
+const MEDIA_EXTENSIONS: [&str; 9] = [
+    "jpg", "jpeg", "png", "gif", "webp", "avif", "svg", "mp4", "webm",
+];
+
+/// How many comment media files were saved, already saved by an earlier run, or couldn't be downloaded
+#[derive(Debug, Default)]
+pub struct MediaArchive {
+    pub saved: usize,
+    pub reused: usize,
+    pub failed: usize,
+}
+
+/// The extension of the last path segment of `url`, if it looks like one
+fn extension(url: &Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    let (_, extension) = segment.rsplit_once('.')?;
+
+    (!extension.is_empty()
+        && extension.len() <= 5
+        && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+    .then(|| extension.to_lowercase())
+}
+
+/// Keeps the characters of a file name which are safe on all platforms
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Parses the value of an `src` or `href` attribute if it points to media which should be archived
+fn media_url(attribute: &str, value: &str) -> Option<Url> {
+    let url = Url::parse(&decode_html_entities(value)).ok()?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+
+    // Images and videos are always embedded with src, but links are only followed to files
+    let is_media = attribute.eq_ignore_ascii_case("src")
+        || extension(&url).is_some_and(|extension| MEDIA_EXTENSIONS.contains(&extension.as_str()));
+
+    is_media.then_some(url)
+}
+
+/// Downloads `url` to `folder`/`relative_path` unless it already exists there
+///
+/// Returns whether the file had to be downloaded.
+async fn save(
+    fetcher: &dyn Fetcher,
+    url: &Url,
+    folder: &Path,
+    relative_path: &str,
+) -> anyhow::Result<bool> {
+    let path = folder.join(relative_path);
+
+    if path.exists() {
+        return Ok(false);
+    }
+
+    let bytes = fetcher
+        .get_file(url.as_str())
+        .await
+        .with_context(|| format!("Failed to download {url}"))?;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&path, bytes)
+        .await
+        .with_context(|| format!("Failed to write \"{}\"", path.to_string_lossy()))?;
+
+    Ok(true)
+}
+
+/// Archives a file, remembering the outcome so every URL is only requested once
+async fn archive(
+    fetcher: &dyn Fetcher,
+    folder: &Path,
+    url: &Url,
+    relative_path: String,
+    report: &mut MediaArchive,
+) -> Option<String> {
+    match save(fetcher, url, folder, &relative_path).await {
+        Ok(true) => report.saved += 1,
+        Ok(false) => report.reused += 1,
+        Err(e) => {
+            warn!("{e:#}");
+            report.failed += 1;
+            return None;
+        }
+    }
+
+    Some(relative_path)
+}
+
+/// Downloads the avatars of the commenters and the media embedded in comments to the `comment-media` folder
+/// of a post, and points the comments to the local copies
+///
+/// Avatars are saved once per user; files which can't be downloaded keep their remote URL.
+pub async fn archive_comment_media(
+    fetcher: &dyn Fetcher,
+    folder: &Path,
+    thread: &mut [ThreadedComment],
+) -> MediaArchive {
+    let mut report = MediaArchive::default();
+
+    // The local path of every user's avatar and of every other file, or `None` if it couldn't be saved
+    let mut avatars: HashMap<String, Option<String>> = HashMap::new();
+    let mut media: HashMap<String, Option<String>> = HashMap::new();
+
+    let mut pending: Vec<&mut ThreadedComment> = thread.iter_mut().collect();
+
+    while let Some(comment) = pending.pop() {
+        let avatar = Url::parse(&comment.profile_pic)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"));
+
+        if let Some(url) = avatar {
+            // Guests have no user id, so their avatars are told apart by URL
+            let user = match comment.user_id.as_str() {
+                "" | "0" => url_hash(url.as_str()),
+                user_id => sanitize(user_id),
+            };
+
+            if !avatars.contains_key(&user) {
+                let relative_path = format!(
+                    "{}/avatars/{user}.{}",
+                    constants::COMMENT_MEDIA_FOLDER,
+                    extension(&url).unwrap_or_else(|| "jpg".to_string())
+                );
+                let local = archive(fetcher, folder, &url, relative_path, &mut report).await;
+                avatars.insert(user.clone(), local);
+            }
+
+            if let Some(local) = &avatars[&user] {
+                comment.profile_pic = local.clone();
+            }
+        }
+
+        let urls: Vec<(String, Url)> = MEDIA_ATTRIBUTE_RX
+            .captures_iter(&comment.content)
+            .filter_map(|captures| {
+                media_url(&captures[1], &captures[2]).map(|url| (captures[2].to_string(), url))
+            })
+            .collect();
+
+        for (value, url) in urls {
+            if media.contains_key(&value) {
+                continue;
+            }
+
+            let name = url
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|segment| !segment.is_empty())
+                .map(sanitize)
+                .unwrap_or_else(|| "media".to_string());
+            let relative_path = format!(
+                "{}/{}-{name}",
+                constants::COMMENT_MEDIA_FOLDER,
+                url_hash(url.as_str())
+            );
+
+            let local = archive(fetcher, folder, &url, relative_path, &mut report).await;
+            media.insert(value, local);
+        }
+
+        comment.content = MEDIA_ATTRIBUTE_RX
+            .replace_all(&comment.content, |captures: &Captures| {
+                match media.get(&captures[2]) {
+                    Some(Some(local)) => format!("{}=\"{local}\"", &captures[1]),
+                    _ => captures[0].to_string(),
+                }
+            })
+            .into_owned();
+
+        pending.extend(comment.replies.iter_mut());
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{temp_dir, StubFetcher};
+
+    fn comment(id: &str, user_id: &str, profile_pic: &str, content: &str) -> ThreadedComment {
+        ThreadedComment {
+            id: id.to_string(),
+            parent_id: None,
+            depth: 0,
+            author: format!("author {id}"),
+            user_id: user_id.to_string(),
+            role: String::new(),
+            profile_pic: profile_pic.to_string(),
+            posted_on: String::new(),
+            likes: 0,
+            content: content.to_string(),
+            replies: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn archives_avatars_and_embedded_media() {
+        let folder = temp_dir("comment-media");
+        let avatar = "https://cdn.example.com/avatars/7.png";
+        let guest_avatar = "https://secure.gravatar.com/avatar/abc";
+        let image = "https://cdn.example.com/uploads/pic.jpg";
+        let broken = "https://cdn.example.com/uploads/broken.gif";
+
+        let mut stub = StubFetcher::default();
+        stub.files.insert(avatar.to_string(), b"avatar".to_vec());
+        stub.files
+            .insert(guest_avatar.to_string(), b"guest".to_vec());
+        stub.files.insert(image.to_string(), b"image".to_vec());
+
+        let content = format!(
+            r#"<img src="{image}"> <a href="https://example.com/page">page</a> <a href="{broken}">gif</a>"#
+        );
+        let mut first = comment("1", "7", avatar, &content);
+        first.replies.push(comment("2", "7", avatar, "Same user"));
+        let mut thread = vec![first, comment("3", "0", guest_avatar, "A guest")];
+
+        let report = archive_comment_media(&stub, &folder, &mut thread).await;
+        assert_eq!((report.saved, report.reused, report.failed), (3, 0, 1));
+
+        // The avatar of a user is only requested once
+        let avatar_requests = stub
+            .requests()
+            .iter()
+            .filter(|url| url.as_str() == avatar)
+            .count();
+        assert_eq!(avatar_requests, 1);
+        assert!(!stub
+            .requests()
+            .contains(&"https://example.com/page".to_string()));
+
+        assert_eq!(thread[0].profile_pic, "comment-media/avatars/7.png");
+        assert_eq!(
+            thread[0].replies[0].profile_pic,
+            "comment-media/avatars/7.png"
+        );
+        let guest_path = format!("comment-media/avatars/{}.jpg", url_hash(guest_avatar));
+        assert_eq!(thread[1].profile_pic, guest_path);
+        assert_eq!(std::fs::read(folder.join(&guest_path)).unwrap(), b"guest");
+
+        let image_path = format!("comment-media/{}-pic.jpg", url_hash(image));
+        assert_eq!(std::fs::read(folder.join(&image_path)).unwrap(), b"image");
+        assert_eq!(
+            thread[0].content,
+            format!(
+                r#"<img src="{image_path}"> <a href="https://example.com/page">page</a> <a href="{broken}">gif</a>"#
+            )
+        );
+    }
+}
@@ -0,0 +1,855 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use html_escape::{decode_html_entities, encode_double_quoted_attribute, encode_text};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::{Id, Thing};
+
+use crate::{
+    comment_media::{archive_comment_media, MediaArchive},
+    constants,
+    data::Comment,
+    db::{self, CommentChanges, CommentSync, Db},
+    fetcher::Fetcher,
+    library::{find_exports, read_export},
+    parser::{self, html_to_text},
+};
+
+lazy_static! {
+    /// HTML comments and elements whose content isn't text
+    static ref SKIPPED_HTML_RX: Regex =
+        Regex::new(r"(?is)<!--.*?-->|<(script|style|iframe|object)\b[^>]*>.*?</(script|style|iframe|object)\s*>").unwrap();
+    static ref TAG_RX: Regex =
+        Regex::new(r#"<(/?)([a-zA-Z][a-zA-Z0-9]*)((?:[^>"']|"[^"]*"|'[^']*')*)>"#).unwrap();
+    static ref ATTRIBUTE_RX: Regex =
+        Regex::new(r#"([a-zA-Z_:][-a-zA-Z0-9_:.]*)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+))"#).unwrap();
+    static ref PARAGRAPH_END_RX: Regex = Regex::new(r"(?i)</p\s*>").unwrap();
+    static ref LINE_BREAK_RX: Regex = Regex::new(r"(?i)<br\s*/?>").unwrap();
+}
+
+/// The tags kept in the HTML export, with the attributes they may keep
+const ALLOWED_TAGS: [(&str, &[&str]); 18] = [
+    ("a", &["href", "title"]),
+    ("img", &["src", "alt", "title", "width", "height"]),
+    ("p", &[]),
+    ("br", &[]),
+    ("b", &[]),
+    ("strong", &[]),
+    ("i", &[]),
+    ("em", &[]),
+    ("u", &[]),
+    ("s", &[]),
+    ("del", &[]),
+    ("blockquote", &[]),
+    ("code", &[]),
+    ("pre", &[]),
+    ("ul", &[]),
+    ("ol", &[]),
+    ("li", &[]),
+    ("span", &[]),
+];
+
+/// The formats the comments of a post can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentFormat {
+    /// The nested comment tree
+    Json,
+
+    /// One row per comment, with its parent and depth
+    Csv,
+
+    /// A readable transcript
+    Html,
+
+    /// A readable transcript
+    Markdown,
+}
+
+impl CommentFormat {
+    pub const NAMES: [&'static str; 4] = ["json", "csv", "html", "markdown"];
+
+    fn extension(self) -> &'static str {
+        match self {
+            CommentFormat::Json => "json",
+            CommentFormat::Csv => "csv",
+            CommentFormat::Html => "html",
+            CommentFormat::Markdown => "md",
+        }
+    }
+}
+
+impl FromStr for CommentFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(CommentFormat::Json),
+            "csv" => Ok(CommentFormat::Csv),
+            "html" => Ok(CommentFormat::Html),
+            "markdown" | "md" => Ok(CommentFormat::Markdown),
+            _ => anyhow::bail!(
+                "Unknown comment format {s}; expected one of {}",
+                CommentFormat::NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+/// How the comments of downloaded posts are handled
+#[derive(Debug, Clone)]
+pub struct CommentOptions {
+    /// The exports written next to every downloaded post
+    pub formats: Vec<CommentFormat>,
+
+    /// The maximum number of comment pages requested at the same time
+    pub concurrency: usize,
+
+    /// Whether avatars and embedded media are downloaded next to the exports
+    pub archive_media: bool,
+}
+
+/// The files written by [`write_comment_exports`]
+#[derive(Debug)]
+pub struct CommentExports {
+    pub files: Vec<String>,
+
+    /// What happened to the avatars and embedded media, if they were archived
+    pub media: Option<MediaArchive>,
+}
+
+/// A comment with its replies, ordered by the time they were posted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadedComment {
+    pub id: String,
+
+    /// The id of the comment this replies to, or `None` for top-level comments
+    pub parent_id: Option<String>,
+
+    /// 0 for top-level comments, 1 for their replies, and so on
+    pub depth: usize,
+
+    pub author: String,
+    pub user_id: String,
+    pub role: String,
+    pub profile_pic: String,
+    pub posted_on: String,
+    pub likes: i64,
+
+    /// The HTML content
+    pub content: String,
+
+    pub replies: Vec<ThreadedComment>,
+}
+
+/// The JSON document of the threaded comments of a post
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommentsExportV1 {
+    pub hdpc_dl_version: i32,
+    pub program_version: String,
+    pub title: String,
+    pub source_url: String,
+    pub comment_count: usize,
+    pub comments: Vec<ThreadedComment>,
+}
+
+/// Parses the formats `posted_on` was seen in; unknown formats are ordered by comment id only
+fn parse_posted_on(posted_on: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(posted_on) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%B %d, %Y at %I:%M %p",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(posted_on, format).ok())
+    .map(|date| date.and_utc())
+}
+
+/// The order of comments: by date (unknown dates first), then by id, which grows over time
+fn sort_key(comment: &Comment) -> (Option<DateTime<Utc>>, u64, String) {
+    (
+        parse_posted_on(&comment.posted_on),
+        comment.comment_id.parse().unwrap_or(u64::MAX),
+        comment.comment_id.clone(),
+    )
+}
+
+/// Collects all comments and their (nested) children
+fn flatten_raw<'a>(comments: impl Iterator<Item = &'a Comment>, flat: &mut Vec<&'a Comment>) {
+    for comment in comments {
+        flat.push(comment);
+
+        if let Some(children) = &comment.children {
+            flatten_raw(children.values(), flat);
+        }
+    }
+}
+
+/// Builds the comment tree from the comments of the API, which may nest replies or list them flat
+///
+/// Replies whose parent is missing, or whose parents are replies to them in turn, are treated as top-level comments.
+pub fn build_thread(comments: &[Comment]) -> Vec<ThreadedComment> {
+    let mut flat = vec![];
+    flatten_raw(comments.iter(), &mut flat);
+
+    // Replies may be listed both nested and flat, so only the first copy is kept
+    let mut seen = HashSet::new();
+    flat.retain(|comment| seen.insert(comment.comment_id.as_str()));
+    flat.sort_by_cached_key(|comment| sort_key(comment));
+
+    // Top-level comments have "0" as parent
+    let parents: HashMap<&str, &str> = flat
+        .iter()
+        .map(|comment| {
+            (
+                comment.comment_id.as_str(),
+                comment.comment_parrent.as_str(),
+            )
+        })
+        .filter(|(id, parent)| *parent != "0" && parent != id && seen.contains(parent))
+        .collect();
+
+    // Comments in a cycle of parents could never be reached from a top-level comment
+    let in_cycle = |id: &str| {
+        let mut current = id;
+        for _ in 0..parents.len() {
+            match parents.get(current) {
+                Some(&parent) if parent == id => return true,
+                Some(&parent) => current = parent,
+                None => return false,
+            }
+        }
+        false
+    };
+
+    let mut children: HashMap<&str, Vec<&Comment>> = HashMap::new();
+    let mut roots = vec![];
+
+    for comment in flat.iter().copied() {
+        match parents.get(comment.comment_id.as_str()) {
+            Some(parent) if !in_cycle(&comment.comment_id) => {
+                children.entry(parent).or_default().push(comment)
+            }
+            _ => roots.push(comment),
+        }
+    }
+
+    fn build(
+        comment: &Comment,
+        parent_id: Option<String>,
+        depth: usize,
+        children: &HashMap<&str, Vec<&Comment>>,
+    ) -> ThreadedComment {
+        let replies = children
+            .get(comment.comment_id.as_str())
+            .into_iter()
+            .flatten()
+            .map(|reply| build(reply, Some(comment.comment_id.clone()), depth + 1, children))
+            .collect();
+
+        ThreadedComment {
+            id: comment.comment_id.clone(),
+            parent_id,
+            depth,
+            author: comment.comment_author.clone(),
+            user_id: comment.user_id.clone(),
+            role: comment.role.clone(),
+            profile_pic: comment.profile_pic.clone(),
+            posted_on: comment.posted_on.clone(),
+            likes: comment.likes,
+            content: comment.content.clone(),
+            replies,
+        }
+    }
+
+    roots
+        .into_iter()
+        .map(|comment| build(comment, None, 0, &children))
+        .collect()
+}
+
+/// Lists the comments of a tree depth-first, i.e. in the order of a transcript
+pub fn flatten_thread(thread: &[ThreadedComment]) -> Vec<&ThreadedComment> {
+    let mut flat = vec![];
+    let mut pending: Vec<_> = thread.iter().rev().collect();
+
+    while let Some(comment) = pending.pop() {
+        flat.push(comment);
+        pending.extend(comment.replies.iter().rev());
+    }
+
+    flat
+}
+
+fn to_csv(thread: &[ThreadedComment]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+
+    writer.write_record([
+        "id",
+        "parent_id",
+        "depth",
+        "author",
+        "user_id",
+        "role",
+        "posted_on",
+        "likes",
+        "text",
+        "content",
+    ])?;
+
+    for comment in flatten_thread(thread) {
+        writer.write_record([
+            comment.id.as_str(),
+            comment.parent_id.as_deref().unwrap_or(""),
+            &comment.depth.to_string(),
+            &comment.author,
+            &comment.user_id,
+            &comment.role,
+            &comment.posted_on,
+            &comment.likes.to_string(),
+            &html_to_text(&comment.content),
+            &comment.content,
+        ])?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+/// Whether a link may be kept: web links and relative paths, like those of archived media, but no `javascript:` or `data:`
+fn is_safe_url(url: &str) -> bool {
+    // Browsers ignore whitespace and control characters in schemes
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => {
+            matches!(scheme, "http" | "https")
+        }
+        _ => true,
+    }
+}
+
+/// Keeps only harmless tags and attributes of HTML written by anyone, like comments, and escapes everything else
+pub fn sanitize_html(html: &str) -> String {
+    let html = SKIPPED_HTML_RX.replace_all(html, "");
+    let escape = |text: &str| text.replace('<', "&lt;").replace('>', "&gt;");
+
+    let mut clean = String::with_capacity(html.len());
+    let mut last = 0;
+
+    for tag in TAG_RX.captures_iter(&html) {
+        let whole = tag.get(0).unwrap();
+        clean.push_str(&escape(&html[last..whole.start()]));
+        last = whole.end();
+
+        let name = tag[2].to_ascii_lowercase();
+        let Some((_, attributes)) = ALLOWED_TAGS.iter().find(|(allowed, _)| *allowed == name)
+        else {
+            continue;
+        };
+
+        if !tag[1].is_empty() {
+            let _ = write!(clean, "</{name}>");
+            continue;
+        }
+
+        let _ = write!(clean, "<{name}");
+        for attribute in ATTRIBUTE_RX.captures_iter(&tag[3]) {
+            let attribute_name = attribute[1].to_ascii_lowercase();
+            if !attributes.contains(&attribute_name.as_str()) {
+                continue;
+            }
+
+            let value = (2..=4)
+                .find_map(|group| attribute.get(group))
+                .map_or("", |value| value.as_str());
+            let value = decode_html_entities(value);
+            if matches!(attribute_name.as_str(), "href" | "src") && !is_safe_url(&value) {
+                continue;
+            }
+
+            let _ = write!(
+                clean,
+                " {attribute_name}=\"{}\"",
+                encode_double_quoted_attribute(&value)
+            );
+        }
+        clean.push('>');
+    }
+
+    clean.push_str(&escape(&html[last..]));
+    clean
+}
+
+fn to_html(title: &str, source_url: &str, thread: &[ThreadedComment]) -> String {
+    let mut html = String::new();
+
+    // Writing to a String can't fail
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Comments on {title}</title>\n\
+        <style>\n\
+        body {{ font-family: sans-serif; max-width: 50em; margin: auto; }}\n\
+        .comment {{ border-left: 2px solid #ccc; padding-left: 1em; margin: 1em 0; }}\n\
+        .meta {{ color: #666; font-size: 0.9em; }}\n\
+        .meta img {{ width: 2em; height: 2em; vertical-align: middle; border-radius: 50%; }}\n\
+        </style>\n</head>\n<body>\n<h1>Comments on <a href=\"{url}\">{title}</a></h1>\n",
+        title = encode_text(title),
+        url = encode_double_quoted_attribute(source_url),
+    );
+
+    fn write_comment(html: &mut String, comment: &ThreadedComment) {
+        let _ = write!(
+            html,
+            "<div class=\"comment\" id=\"comment-{id}\">\n<p class=\"meta\"><img src=\"{pic}\" alt=\"\"> \
+            <strong>{author}</strong> · {posted_on} · {likes} likes</p>\n<div class=\"content\">{content}</div>\n",
+            id = encode_double_quoted_attribute(&comment.id),
+            pic = encode_double_quoted_attribute(&comment.profile_pic),
+            author = encode_text(&comment.author),
+            posted_on = encode_text(&comment.posted_on),
+            likes = comment.likes,
+            // The content is HTML written by the commenter, so only harmless tags are kept
+            content = sanitize_html(&comment.content),
+        );
+
+        for reply in comment.replies.iter() {
+            write_comment(html, reply);
+        }
+
+        html.push_str("</div>\n");
+    }
+
+    for comment in thread {
+        write_comment(&mut html, comment);
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Splits the HTML of a comment into paragraphs of lines, as [`html_to_text`] collapses all breaks
+fn to_paragraphs(html: &str) -> Vec<Vec<String>> {
+    PARAGRAPH_END_RX
+        .split(html)
+        .map(|paragraph| {
+            LINE_BREAK_RX
+                .split(paragraph)
+                .map(html_to_text)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|lines| !lines.is_empty())
+        .collect()
+}
+
+fn to_markdown(title: &str, source_url: &str, thread: &[ThreadedComment]) -> String {
+    let mut markdown = format!("# Comments on [{title}]({source_url})\n");
+
+    for comment in flatten_thread(thread) {
+        let indent = "> ".repeat(comment.depth);
+
+        let _ = write!(
+            markdown,
+            "\n{indent}**{author}** · {posted_on} · {likes} likes\n{indent}\n",
+            author = comment.author,
+            posted_on = comment.posted_on,
+            likes = comment.likes,
+        );
+
+        for (index, lines) in to_paragraphs(&comment.content).iter().enumerate() {
+            if index > 0 {
+                let _ = writeln!(markdown, "{}", indent.trim_end());
+            }
+
+            // A trailing backslash is a line break within the paragraph
+            for (index, line) in lines.iter().enumerate() {
+                let end = if index + 1 < lines.len() { "\\" } else { "" };
+                let _ = writeln!(markdown, "{indent}{line}{end}");
+            }
+        }
+    }
+
+    markdown
+}
+
+/// Writes the comments of a post in the formats of `options` to `folder`
+///
+/// If media is archived, the exports reference the local copies instead of the site. Media is only
+/// archived with a fetcher to request it.
+pub async fn write_comment_exports(
+    fetcher: Option<&dyn Fetcher>,
+    folder: &Path,
+    title: &str,
+    source_url: &str,
+    comments: &[Comment],
+    options: &CommentOptions,
+) -> anyhow::Result<CommentExports> {
+    let mut thread = build_thread(comments);
+    let mut written = vec![];
+
+    let media = match fetcher.filter(|_| options.archive_media) {
+        Some(fetcher) => Some(archive_comment_media(fetcher, folder, &mut thread).await),
+        None => None,
+    };
+
+    for format in options.formats.iter() {
+        let contents = match format {
+            CommentFormat::Json => serde_json::to_vec_pretty(&CommentsExportV1 {
+                hdpc_dl_version: constants::COMMENTS_VERSION,
+                program_version: constants::VERSION.to_string(),
+                title: title.to_string(),
+                source_url: source_url.to_string(),
+                comment_count: flatten_thread(&thread).len(),
+                comments: thread.clone(),
+            })?,
+            CommentFormat::Csv => to_csv(&thread)?,
+            CommentFormat::Html => to_html(title, source_url, &thread).into_bytes(),
+            CommentFormat::Markdown => to_markdown(title, source_url, &thread).into_bytes(),
+        };
+
+        let path = folder.join(format!(
+            "{}.{}",
+            constants::COMMENTS_FILE_STEM,
+            format.extension()
+        ));
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write \"{}\"", path.to_string_lossy()))?;
+
+        written.push(path.to_string_lossy().to_string());
+    }
+
+    Ok(CommentExports {
+        files: written,
+        media,
+    })
+}
+
+/// Writes the comment exports of already downloaded posts, found in files or folders
+pub async fn export_comments(
+    fetcher: Option<&dyn Fetcher>,
+    paths: Vec<PathBuf>,
+    options: &CommentOptions,
+) -> anyhow::Result<()> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            files.append(&mut find_exports(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+
+    let (mut exported, mut skipped) = (0, 0);
+
+    for file in files.iter() {
+        let post = match read_export(file) {
+            Ok(post) => post,
+            Err(e) => {
+                println!("Skipped \"{}\": {e:#}", file.to_string_lossy());
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let Some(comments) = &post.comments else {
+            println!(
+                "Skipped \"{}\": it was downloaded without comments",
+                file.to_string_lossy()
+            );
+            skipped += 1;
+            continue;
+        };
+
+        let exports = write_comment_exports(
+            fetcher,
+            Path::new(&post.folder),
+            &post.title,
+            &post.source_url,
+            comments,
+            options,
+        )
+        .await?;
+        exported += 1;
+
+        if let Some(media) = exports.media {
+            print_media_archive(&post.title, &media);
+        }
+    }
+
+    println!("Exported the comments of {exported} posts, skipped {skipped}.");
+
+    Ok(())
+}
+
+/// Prints how many avatars and embedded media files of a post were archived
+pub fn print_media_archive(prefix: &str, media: &MediaArchive) {
+    let mut message = format!(
+        "{prefix}: saved {} comment media file(s), {} were already saved",
+        media.saved, media.reused
+    );
+
+    if media.failed > 0 {
+        let _ = write!(message, ", {} failed", media.failed);
+    }
+
+    println!("{message}");
+}
+
+/// Whether the API lists the newest comments first, judging by the ids of the top-level comments of a page
+fn newest_first(comments: &[Comment]) -> bool {
+    let ids: Vec<u64> = comments
+        .iter()
+        .filter_map(|comment| comment.comment_id.parse().ok())
+        .collect();
+
+    match (ids.first(), ids.last()) {
+        (Some(first), Some(last)) => first > last,
+        _ => true,
+    }
+}
+
+fn contains_known(comments: &[Comment], known: &HashSet<String>) -> bool {
+    comments.iter().any(|comment| {
+        known.contains(&comment.comment_id)
+            || comment
+                .children
+                .iter()
+                .flat_map(|children| children.values())
+                .any(|child| contains_known(std::slice::from_ref(child), known))
+    })
+}
+
+/// Fetches the comments of a stored post and records which were added, edited, or deleted
+///
+/// Unless `full` is set, only the pages up to the first already stored comment are fetched,
+/// so deleted comments and new replies to old comments are only noticed by a full sync.
+pub async fn sync_post_comments(
+    db: &Db,
+    fetcher: &Arc<dyn Fetcher>,
+    post: &Thing,
+    full: bool,
+    concurrency: usize,
+) -> anyhow::Result<CommentChanges> {
+    let Id::Number(post_id) = post.id else {
+        anyhow::bail!("{post} has no post id; migrate its export with --allow-fetch first");
+    };
+    let post_id = post_id as u64;
+
+    let known: HashSet<String> = db::stored_comments(db, post)
+        .await?
+        .into_iter()
+        .filter(|(_, comment)| comment.deleted != Some(true))
+        .map(|(id, _)| id)
+        .collect();
+
+    if full || known.is_empty() {
+        let comments = parser::get_comments(post_id, fetcher, concurrency).await?;
+        return db::sync_comments(db, post, &comments, CommentSync::Complete).await;
+    }
+
+    let first = parser::get_comments_page(post_id, 1, fetcher.as_ref()).await?;
+    let total_pages = first.total_pages;
+
+    // The new comments are on the first pages if the newest are listed first, otherwise on the last
+    let newest_first = newest_first(&first.comments);
+    let pages: Vec<i64> = if newest_first {
+        (2..=total_pages).collect()
+    } else {
+        (2..=total_pages).rev().collect()
+    };
+
+    let mut done = newest_first && contains_known(&first.comments, &known);
+    let mut fetched = 1;
+    let mut comments = first.comments;
+
+    // Pages are fetched in batches, so a few pages more than needed may be fetched
+    for batch in pages.chunks(concurrency.max(1)) {
+        if done {
+            break;
+        }
+
+        for mut res in parser::get_comment_pages(post_id, batch, fetcher, concurrency).await? {
+            done = done || contains_known(&res.comments, &known);
+            comments.append(&mut res.comments);
+        }
+        fetched += batch.len() as i64;
+    }
+
+    let sync = if fetched >= total_pages {
+        CommentSync::Complete
+    } else {
+        CommentSync::Partial
+    };
+
+    db::sync_comments(db, post, &comments, sync).await
+}
+
+/// Syncs the comments of the given posts (by id or URL), or of all posts with stored comments
+pub async fn sync_comments(
+    db: &Db,
+    fetcher: &Arc<dyn Fetcher>,
+    posts: Vec<String>,
+    full: bool,
+    concurrency: usize,
+) -> anyhow::Result<()> {
+    let posts = if posts.is_empty() {
+        db::posts_with_comments(db).await?
+    } else {
+        let mut things = vec![];
+        for post in posts {
+            things.push(db::find_post(db, &post).await?);
+        }
+        things
+    };
+
+    for post in posts.iter() {
+        match sync_post_comments(db, fetcher, post, full, concurrency).await {
+            Ok(changes) => println!(
+                "{post}: {} added, {} edited, {} deleted, {} restored",
+                changes.added, changes.edited, changes.deleted, changes.restored
+            ),
+            Err(e) => println!("{post}: skipped: {e:#}"),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(id: &str, parent: &str) -> Comment {
+        Comment {
+            comment_id: id.to_string(),
+            comment_post_id: "1".to_string(),
+            post_url: String::new(),
+            comment_author: format!("author {id}"),
+            content: String::new(),
+            approved: "1".to_string(),
+            comment_parrent: parent.to_string(),
+            likes: 0,
+            posted_on: String::new(),
+            user_id: "0".to_string(),
+            profile_pic: String::new(),
+            role: String::new(),
+            children: None,
+        }
+    }
+
+    fn ids(thread: &[ThreadedComment]) -> Vec<(&str, usize)> {
+        flatten_thread(thread)
+            .into_iter()
+            .map(|comment| (comment.id.as_str(), comment.depth))
+            .collect()
+    }
+
+    #[test]
+    fn threads_flat_replies_in_order() {
+        let thread = build_thread(&[
+            comment("3", "1"),
+            comment("1", "0"),
+            comment("2", "0"),
+            comment("4", "3"),
+        ]);
+
+        assert_eq!(ids(&thread), [("1", 0), ("3", 1), ("4", 2), ("2", 0)]);
+        assert_eq!(thread[0].replies[0].parent_id.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn keeps_the_first_copy_of_nested_and_flat_replies() {
+        let mut parent = comment("1", "0");
+        parent.children = Some(HashMap::from([("2".to_string(), comment("2", "1"))]));
+
+        let thread = build_thread(&[parent, comment("2", "1")]);
+
+        assert_eq!(ids(&thread), [("1", 0), ("2", 1)]);
+    }
+
+    #[test]
+    fn treats_orphans_and_self_replies_as_top_level() {
+        let thread = build_thread(&[comment("1", "9"), comment("2", "2")]);
+
+        assert_eq!(ids(&thread), [("1", 0), ("2", 0)]);
+    }
+
+    #[test]
+    fn breaks_parent_cycles() {
+        let thread = build_thread(&[
+            comment("1", "2"),
+            comment("2", "1"),
+            comment("3", "1"),
+            comment("4", "0"),
+        ]);
+
+        assert_eq!(ids(&thread), [("1", 0), ("3", 1), ("2", 0), ("4", 0)]);
+    }
+
+    #[test]
+    fn keeps_harmless_html() {
+        assert_eq!(
+            sanitize_html(r#"<p>Nice <b>work</b><br/><img src="comment-media/a.png" alt="a"></p>"#),
+            r#"<p>Nice <b>work</b><br><img src="comment-media/a.png" alt="a"></p>"#
+        );
+        assert_eq!(
+            sanitize_html(r#"<a href='https://example.com/?a=1&amp;b=2' target=_blank>link</a>"#),
+            r#"<a href="https://example.com/?a=1&amp;b=2">link</a>"#
+        );
+    }
+
+    #[test]
+    fn removes_scripts_and_event_handlers() {
+        assert_eq!(
+            sanitize_html(r#"<script>alert(1)</script><img src=x onerror="alert(1)"><!-- <b> -->"#),
+            r#"<img src="x">"#
+        );
+        assert_eq!(
+            sanitize_html(
+                r#"<a href=" java&#x09;script:alert(1)">x</a><iframe src="https://example.com"></iframe>"#
+            ),
+            "<a>x</a>"
+        );
+        assert_eq!(
+            sanitize_html(r#"<svg onload="alert(1)">a < b</svg>"#),
+            "a &lt; b"
+        );
+    }
+
+    #[test]
+    fn keeps_the_paragraphs_and_line_breaks_of_comments_in_markdown() {
+        let mut parent = comment("1", "0");
+        parent.content =
+            "<p>First  line<br />second line</p>\n<p>Second &amp; last</p>".to_string();
+        let mut reply = comment("2", "1");
+        reply.content = "One<br>Two".to_string();
+
+        let markdown = to_markdown(
+            "Title",
+            "https://hdporncomics.com/title/",
+            &build_thread(&[parent, reply]),
+        );
+
+        assert!(
+            markdown.contains("\nFirst line\\\nsecond line\n\nSecond & last\n"),
+            "{markdown}"
+        );
+        assert!(markdown.contains("\n> One\\\n> Two\n"), "{markdown}");
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::db::AuthLevel;
+
+/// The name of the config file looked for in the destination folder
+pub const CONFIG_FILE_NAME: &str = "hdpc-dl.toml";
+
+/// The folder of this user the database and the login session are kept in by default,
+/// e.g. `~/.local/share/hdpc-dl` on Linux or `%APPDATA%\\hdpc-dl` on Windows
+pub fn default_data_dir() -> Option<PathBuf> {
+    dirs_next::data_dir().map(|dir| dir.join("hdpc-dl"))
+}
+
+/// A value which must never be printed, e.g. a password
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    /// The actual value; only use it where it's sent to its destination
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"********\"")
+    }
+}
+
+/// The contents of the config file; every value can be overridden with CLI args or environment variables
+///
+/// ```toml
+/// [database]
+/// endpoint = "ws://127.0.0.1:8000"
+/// namespace = "hdpc-dl"
+/// database = "hdpc-dl"
+/// username = "hdpc"
+/// password = "..."
+/// level = "database"
+///
+/// [http]
+/// user_agent = "Mozilla/5.0 ..."
+/// cookies = "cookies.txt"
+/// headers = { "Accept-Language" = "en" }
+/// proxies = ["socks5h://127.0.0.1:9050"]
+/// host_proxies = { "cdn.example.com" = "direct" }
+///
+/// [account]
+/// username = "..."
+/// password = "..."
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub database: DatabaseConfig,
+
+    #[serde(default)]
+    pub http: HttpConfig,
+
+    #[serde(default)]
+    pub account: AccountConfig,
+}
+
+/// The `[database]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// `mem://`, `mem://<file.surql>`, `rocksdb://<folder>`, or a `ws://` server
+    pub endpoint: Option<String>,
+    pub namespace: Option<String>,
+    pub database: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+
+    /// The level the user is defined on
+    pub level: Option<AuthLevel>,
+}
+
+/// The `[http]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    pub user_agent: Option<String>,
+
+    /// A Netscape `cookies.txt` or a JSON cookie export
+    pub cookies: Option<PathBuf>,
+
+    /// Extra headers sent with every request
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    /// Proxy URLs, used in turns
+    #[serde(default)]
+    pub proxies: Vec<String>,
+
+    /// Hosts and their proxy URL, or `direct`
+    #[serde(default)]
+    pub host_proxies: BTreeMap<String, String>,
+}
+
+/// The `[account]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+
+    /// The URL of the favorites list of the account, as shown by the site when logged in
+    pub favorites_url: Option<String>,
+
+    /// The URL of the bookmarks list of the account
+    pub bookmarks_url: Option<String>,
+}
+
+impl Config {
+    /// Reads the config file at `path`, or returns the defaults if `required` is false and it doesn't exist
+    pub fn load(path: &Path, required: bool) -> anyhow::Result<Self> {
+        if !required && !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = read_to_string(path).with_context(|| {
+            format!("Reading the config file {} failed", path.to_string_lossy())
+        })?;
+
+        toml::from_str(&text)
+            .with_context(|| format!("Parsing the config file {} failed", path.to_string_lossy()))
+    }
+}
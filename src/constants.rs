@@ -11,4 +11,46 @@ pub const NAME: &str = "HDPC Downloader";
 /// The number of targets per (full) results page
 pub const TARGETS_PER_PAGE: usize = 21;
 
+/// The listing page which takes taxonomy filters as query parameters
+pub const SEARCH_PATH: &str = "https://hdporncomics.com/";
+
+/// The WordPress login form
+pub const LOGIN_PATH: &str = "https://hdporncomics.com/wp-login.php";
+
 pub const API_FILTER_PATH: &str = "https://hdporncomics.com/?rest_route=/api/v1/filter";
+
+/// The name of the JSON document written next to every downloaded post
+pub const EXPORT_FILE_NAME: &str = "hdpc-info.json";
+
+/// The latest version of the JSON document written next to every downloaded post
+pub const EXPORT_VERSION: i32 = 8;
+
+/// The name of the file in the data folder holding the cookies of the logged in account
+pub const SESSION_FILE_NAME: &str = "hdpc-dl-session.json";
+
+/// The latest version of the JSON document with crawl results
+pub const CRAWL_RESULT_VERSION: i32 = 6;
+
+/// The suffix of the file names of crawl results
+pub const CRAWL_RESULTS_SUFFIX: &str = "_crawl_results.json";
+
+/// The latest version of the JSON document with filter lists
+pub const FILTERS_VERSION: i32 = 1;
+
+/// The suffix of the file names of filter lists
+pub const FILTERS_SUFFIX: &str = "_filters.json";
+
+/// The name (without extension) of the comment exports written next to downloaded posts
+pub const COMMENTS_FILE_STEM: &str = "hdpc-comments";
+
+/// The folder of a post the avatars and media of its comments are archived to
+pub const COMMENT_MEDIA_FOLDER: &str = "comment-media";
+
+/// The latest version of the threaded comments JSON document
+pub const COMMENTS_VERSION: i32 = 1;
+
+/// How often a comment page is requested before giving up
+pub const COMMENT_PAGE_ATTEMPTS: u32 = 3;
+
+/// The SOCKS proxy of a local Tor daemon, resolving host names through Tor
+pub const TOR_PROXY: &str = "socks5h://127.0.0.1:9050";
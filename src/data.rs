@@ -4,10 +4,10 @@ use serde::{Deserialize, Serialize};
 
 // TODO extract comments using curl 'https://hdporncomics.com/wp-json/api/v1/comments/0123456789/?page_no=1'
 
-#[derive(Debug, Serialize)]
-pub struct MetadataV5<'a> {
-    pub name: &'a str,
-    pub entries: Vec<&'a str>,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MetadataV5 {
+    pub name: String,
+    pub entries: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -40,20 +40,54 @@ pub struct Post<'a> {
     pub url: &'a str,
 }
 
-#[derive(Debug, Serialize)]
+/// The owned version of [`InfoboxRow`], used to read exports back in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum InfoboxRowBuf {
+    Raw {
+        name: String,
+        html: String,
+    },
+    TagLike {
+        name: String,
+        tags: Vec<TagLikeBuf>,
+    },
+    Text {
+        name: String,
+        class_name: String,
+        text: Option<String>,
+    },
+}
+
+/// The owned version of a chapter [`Post`], used to read exports back in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChapterBuf {
+    pub name: String,
+    pub url: String,
+}
+
+/// A post in a search result page
+///
+/// The defaults are only used when reading older crawl results which lack these fields.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct PostBuf {
+    #[serde(default)]
     pub post_id: u32,
     pub name: String,
     pub url: String,
+    #[serde(default)]
     pub views: u32,
+    #[serde(default)]
     pub upvotes: u32,
+    #[serde(default)]
     pub downvotes: u32,
+    #[serde(default)]
     pub meta_tags: Vec<String>,
+    #[serde(default)]
     pub rendered_tags: Vec<TagLikeBuf>,
 }
 
 /// A tag-like marker of a post in a search result page
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TagLikeBuf {
     pub href: String,
     pub text: String,
@@ -75,13 +109,62 @@ pub struct Ratings {
     pub favorites: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The statistics reported by the view API
+///
+/// The API sends numbers or numeric strings depending on the post; missing or empty values are `None`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiViewResponse {
-    post_dislikes: String,
-    post_favorites: String,
-    post_id: i64,
-    post_likes: String,
-    post_views: String,
+    #[serde(default, deserialize_with = "lenient_count")]
+    pub post_dislikes: Option<u64>,
+    #[serde(default, deserialize_with = "lenient_count")]
+    pub post_favorites: Option<u64>,
+    #[serde(default, deserialize_with = "lenient_count")]
+    pub post_id: Option<u64>,
+    #[serde(default, deserialize_with = "lenient_count")]
+    pub post_likes: Option<u64>,
+    #[serde(default, deserialize_with = "lenient_count")]
+    pub post_views: Option<u64>,
+}
+
+/// The statistics as stored in version 7 exports, which kept the strings of the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiViewResponseV7 {
+    pub post_dislikes: String,
+    pub post_favorites: String,
+    pub post_id: i64,
+    pub post_likes: String,
+    pub post_views: String,
+}
+
+/// Parses a count written as text, e.g. `"1,024"`; empty text is an unknown count
+pub fn parse_count_text(text: &str) -> Result<Option<u64>, std::num::ParseIntError> {
+    let text = text.trim().replace(',', "");
+
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    text.parse().map(Some)
+}
+
+/// Accepts a count as a number, a numeric string (e.g. `"1,024"`), an empty string, or null
+fn lenient_count<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Count {
+        Number(u64),
+        Text(String),
+    }
+
+    match Option::<Count>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Count::Number(count)) => Ok(Some(count)),
+        Some(Count::Text(text)) => parse_count_text(&text)
+            .map_err(|_| serde::de::Error::custom(format!("expected a count, found {text:?}"))),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -96,7 +179,7 @@ pub struct Comments {
     pub comments: Vec<Comment>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Comment {
     #[serde(rename = "comment_ID")]
@@ -129,7 +212,7 @@ pub struct ResPage<'a> {
 }
 
 #[derive(Debug, Serialize)]
-pub struct CrawlResultV5<'a> {
+pub struct CrawlResultV6<'a> {
     /// The version of the JSON document
     pub hdpc_dl_version: i32,
 
@@ -146,26 +229,43 @@ pub struct CrawlResultV5<'a> {
     pub posts: &'a Vec<PostBuf>,
 }
 
+/// The owned version of [`CrawlResultV6`], used to read older crawl results back in
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CrawlResultBuf {
+    pub hdpc_dl_version: i32,
+    #[serde(default)]
+    pub program_version: String,
+    pub source_url: String,
+    pub download_date: String,
+    pub posts: Vec<PostBuf>,
+}
+
+/// Only needed to read the version of an export before parsing the rest of it
+#[derive(Debug, Deserialize)]
+pub struct ExportVersion {
+    pub hdpc_dl_version: i32,
+}
+
 // The data structure for the JSON document to be exported
-// Version 4 (2.2.1)
-#[derive(Debug, Serialize)]
-pub struct ExportV4<'a> {
+// Version 4 (2.2.1); only read in by now
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportV4 {
     pub hdpc_dl_version: i32,
-    pub title: &'a str,
-    pub other_title: &'a str,
-    pub upvotes: &'a str,
-    pub downvotes: &'a str,
-    pub favorites: &'a str,
-    pub comment_count: &'a str,
-    pub download_date: &'a str,
-    pub source_url: &'a str,
-    pub metadata: &'a Vec<MetadataV5<'a>>,
-    pub picture_urls: &'a Vec<&'a str>,
+    pub title: String,
+    pub other_title: String,
+    pub upvotes: String,
+    pub downvotes: String,
+    pub favorites: String,
+    pub comment_count: String,
+    pub download_date: String,
+    pub source_url: String,
+    pub metadata: Vec<MetadataV5>,
+    pub picture_urls: Vec<String>,
 }
 
 // The data structure for the JSON document to be exported
 #[derive(Debug, Serialize)]
-pub struct ExportV7<'a> {
+pub struct ExportV8<'a> {
     /// The version of the JSON document
     pub hdpc_dl_version: i32,
 
@@ -202,20 +302,99 @@ pub struct ExportV7<'a> {
     pub comments: Option<Vec<Comment>>,
 }
 
+/// A version 7 export, which is a version 8 export with the statistics as strings
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportV7 {
+    pub hdpc_dl_version: i32,
+    pub program_version: String,
+    pub post_id: u64,
+    pub title: String,
+    pub api_stats: ApiViewResponseV7,
+    pub comment_count: String,
+    pub download_date: String,
+    pub source_url: String,
+    pub metadata: Vec<InfoboxRowBuf>,
+    pub chapters: Vec<ChapterBuf>,
+    pub picture_urls: Vec<String>,
+    pub comments: Option<Vec<Comment>>,
+}
+
+/// The owned version of [`ExportV8`], used to read exports back in
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportV8Buf {
+    pub hdpc_dl_version: i32,
+    pub program_version: String,
+    pub post_id: u64,
+    pub title: String,
+    pub api_stats: ApiViewResponse,
+    pub comment_count: String,
+    pub download_date: String,
+    pub source_url: String,
+    pub metadata: Vec<InfoboxRowBuf>,
+    pub chapters: Vec<ChapterBuf>,
+    pub picture_urls: Vec<String>,
+    pub comments: Option<Vec<Comment>>,
+}
+
+/// A downloaded post as indexed in the library, upgraded from any export version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryPost {
+    /// The version of the export this was read from
+    pub hdpc_dl_version: i32,
+
+    /// The version of this software used for downloading (unknown before version 5)
+    pub program_version: Option<String>,
+
+    /// The id of the post (unknown before version 5)
+    pub post_id: Option<u64>,
+
+    /// The title of the post
+    pub title: String,
+
+    /// The URL of the post
+    pub source_url: String,
+
+    /// The time this was downloaded, as written by the downloader
+    pub download_date: String,
+
+    /// The folder containing the export and the downloaded pictures
+    pub folder: String,
+
+    pub upvotes: Option<u32>,
+    pub downvotes: Option<u32>,
+    pub favorites: Option<u32>,
+    pub views: Option<u32>,
+
+    /// The reported comments count
+    pub comment_count: String,
+
+    /// Key-value(s) pairs, upgraded to infobox rows
+    pub metadata: Vec<InfoboxRowBuf>,
+
+    /// A list of chapters (may be empty)
+    pub chapters: Vec<ChapterBuf>,
+
+    /// The URLs of the pictures downloaded from the remote host
+    pub picture_urls: Vec<String>,
+
+    /// The comments, if they were downloaded
+    pub comments: Option<Vec<Comment>>,
+}
+
 // The data structure for the JSON document to be exported
-#[derive(Debug, Serialize)]
-pub struct ExportV6<'a> {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportV6 {
     /// The version of the JSON document
     pub hdpc_dl_version: i32,
 
     /// The version of this software used for downloading
-    pub program_version: &'static str,
+    pub program_version: String,
 
     /// The id of the downloaded post
     pub post_id: u64,
 
     /// The title of the target
-    pub title: &'a str,
+    pub title: String,
 
     /// The reported upvote count
     pub upvotes: u32,
@@ -230,38 +409,38 @@ pub struct ExportV6<'a> {
     pub favorites: u32,
 
     /// The reported comments count
-    pub comment_count: &'a str,
+    pub comment_count: String,
 
     /// An RFC 3339 timestamp of the time this was downloaded
     pub download_date: String,
 
     /// The URL of the target described by this document
-    pub source_url: &'a str,
+    pub source_url: String,
 
     /// A list of key-value(s) pairs provided by the remote host
-    pub metadata: &'a Vec<InfoboxRow<'a>>,
+    pub metadata: Vec<InfoboxRowBuf>,
 
     /// A list of chapters (may be empty)
-    pub chapters: Vec<Post<'a>>,
+    pub chapters: Vec<ChapterBuf>,
 
     /// The URLs of the individual pictures downloaded from the remote host
-    pub picture_urls: &'a Vec<&'a str>,
+    pub picture_urls: Vec<String>,
 }
 
 // The data structure for the JSON document to be exported
-#[derive(Debug, Serialize)]
-pub struct ExportV5<'a> {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportV5 {
     /// The version of the JSON document
     pub hdpc_dl_version: i32,
 
     /// The version of this software used for downloading
-    pub program_version: &'static str,
+    pub program_version: String,
 
     /// The id of the downloaded post
     pub post_id: u64,
 
     /// The title of the target
-    pub title: &'a str,
+    pub title: String,
 
     /// The reported upvote count
     pub upvotes: u32,
@@ -276,20 +455,62 @@ pub struct ExportV5<'a> {
     pub favorites: u32,
 
     /// The reported comments count
-    pub comment_count: &'a str,
+    pub comment_count: String,
 
     /// An RFC 3339 timestamp of the time this was downloaded
     pub download_date: String,
 
     /// The URL of the target described by this document
-    pub source_url: &'a str,
+    pub source_url: String,
 
     /// A list of key-value(s) pairs provided by the remote host
-    pub metadata: &'a Vec<MetadataV5<'a>>,
+    pub metadata: Vec<MetadataV5>,
 
     /// A list of chapters (may be empty)
-    pub chapters: Vec<Post<'a>>,
+    pub chapters: Vec<ChapterBuf>,
 
     /// The URLs of the individual pictures downloaded from the remote host
-    pub picture_urls: &'a Vec<&'a str>,
+    pub picture_urls: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> serde_json::Result<ApiViewResponse> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn accepts_numbers_and_numeric_strings() {
+        let stats = parse(
+            r#"{"post_dislikes": 3, "post_favorites": "12", "post_id": "42", "post_likes": " 1,024 ", "post_views": 5000}"#,
+        )
+        .unwrap();
+
+        assert_eq!(stats.post_dislikes, Some(3));
+        assert_eq!(stats.post_favorites, Some(12));
+        assert_eq!(stats.post_id, Some(42));
+        assert_eq!(stats.post_likes, Some(1024));
+        assert_eq!(stats.post_views, Some(5000));
+    }
+
+    #[test]
+    fn treats_missing_empty_and_null_counts_as_unknown() {
+        let stats =
+            parse(r#"{"post_dislikes": "", "post_favorites": null, "post_likes": "  "}"#).unwrap();
+
+        assert_eq!(stats.post_dislikes, None);
+        assert_eq!(stats.post_favorites, None);
+        assert_eq!(stats.post_id, None);
+        assert_eq!(stats.post_likes, None);
+        assert_eq!(stats.post_views, None);
+    }
+
+    #[test]
+    fn rejects_other_values() {
+        assert!(parse(r#"{"post_views": "many"}"#).is_err());
+        assert!(parse(r#"{"post_views": -1}"#).is_err());
+        assert!(parse(r#"{"post_views": [1]}"#).is_err());
+    }
 }
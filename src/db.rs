@@ -1,82 +1,1143 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use surrealdb::engine::remote::ws::{Client, Ws};
-use surrealdb::opt::auth::Root;
-use surrealdb::sql::Thing;
+use surrealdb::engine::any::{self, Any};
+use surrealdb::opt::auth::{Database, Namespace, Root};
+use surrealdb::sql::{Id, Thing};
 use surrealdb::Surreal;
+use tracing::info;
 
-#[derive(Debug, Serialize)]
-struct Name<'a> {
-    first: &'a str,
-    last: &'a str,
+use crate::{
+    config::Secret,
+    constants,
+    data::{Comment, InfoboxRowBuf, LibraryPost, PostBuf},
+    filters::{FilterValue, Taxonomy},
+    schema,
+};
+
+/// The database connection used throughout the application
+pub type Db = Surreal<Any>;
+
+/// The file or folder name of the default database in the data folder
+const DEFAULT_DB_NAME: &str = "hdpc-dl";
+
+/// The default namespace and database name
+pub const DEFAULT_NAMESPACE: &str = "hdpc-dl";
+
+/// Everything needed to connect to the database
+#[derive(Debug, Clone)]
+pub struct DbConfig {
+    pub location: DbLocation,
+    pub namespace: String,
+    pub database: String,
+
+    /// Only used for remote servers, as embedded databases have no users
+    pub credentials: Option<Credentials>,
 }
 
-#[derive(Debug, Serialize)]
-struct Person<'a> {
-    title: &'a str,
-    name: Name<'a>,
-    marketing: bool,
+/// The level a database user is defined on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthLevel {
+    #[default]
+    Root,
+    Namespace,
+    Database,
 }
 
-#[derive(Debug, Serialize)]
-struct Responsibility {
-    marketing: bool,
+impl std::str::FromStr for AuthLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "root" => Ok(Self::Root),
+            "namespace" => Ok(Self::Namespace),
+            "database" => Ok(Self::Database),
+            other => anyhow::bail!("Unknown user level \"{other}\""),
+        }
+    }
+}
+
+/// The user to sign in as
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub level: AuthLevel,
+    pub username: String,
+    pub password: Secret,
+}
+
+/// Where the database lives
+#[derive(Debug, Clone)]
+pub enum DbLocation {
+    /// An embedded RocksDB database in this folder
+    #[cfg(feature = "rocksdb")]
+    RocksDb(PathBuf),
+
+    /// An embedded in-memory database, loaded from and saved to this SurrealQL file if given
+    Memory(Option<PathBuf>),
+
+    /// A remote server, e.g. `ws://127.0.0.1:8000`
+    Remote(String),
+}
+
+impl DbLocation {
+    /// The embedded database stored in the data folder `dir`
+    pub fn default_in(dir: &Path) -> Self {
+        #[cfg(feature = "rocksdb")]
+        return Self::RocksDb(dir.join(format!("{DEFAULT_DB_NAME}.db")));
+
+        #[cfg(not(feature = "rocksdb"))]
+        Self::Memory(Some(dir.join(format!("{DEFAULT_DB_NAME}.surql"))))
+    }
+
+    /// The file or folder of an embedded database
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            #[cfg(feature = "rocksdb")]
+            Self::RocksDb(path) => Some(path),
+            Self::Memory(path) => path.as_deref(),
+            Self::Remote(_) => None,
+        }
+    }
+
+    /// Parses `mem://`, `mem://<file.surql>`, `rocksdb://<folder>`, or a remote `ws(s)://` or `http(s)://` endpoint
+    pub fn from_endpoint(endpoint: &str) -> anyhow::Result<Self> {
+        let (scheme, path) = endpoint
+            .split_once("://")
+            .with_context(|| format!("\"{endpoint}\" is not a database endpoint"))?;
+
+        match scheme {
+            "mem" if path.is_empty() => Ok(Self::Memory(None)),
+            "mem" => Ok(Self::Memory(Some(path.into()))),
+            #[cfg(feature = "rocksdb")]
+            "rocksdb" => Ok(Self::RocksDb(path.into())),
+            #[cfg(not(feature = "rocksdb"))]
+            "rocksdb" => {
+                anyhow::bail!("This build doesn't support RocksDB; enable the rocksdb feature")
+            }
+            "ws" | "wss" | "http" | "https" => Ok(Self::Remote(endpoint.to_string())),
+            other => anyhow::bail!("Unknown database scheme \"{other}\""),
+        }
+    }
+}
+
+/// A taxonomy term stored in the table of its [`Taxonomy`], using the site's id as record id
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaxonomyTerm {
+    pub name: String,
+    pub tag: String,
+}
+
+impl From<&FilterValue> for TaxonomyTerm {
+    fn from(value: &FilterValue) -> Self {
+        Self {
+            name: value.name.clone(),
+            tag: value.tag.clone(),
+        }
+    }
+}
+
+/// A stored taxonomy term together with its record id
+#[derive(Debug, Deserialize)]
+struct TaxonomyTermRecord {
+    id: Thing,
+    name: String,
+    tag: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct Record {
-    #[allow(dead_code)]
     id: Thing,
 }
 
-pub async fn connect() -> surrealdb::Result<Surreal<Client>> {
-    // Connect to the server
-    let db = Surreal::new::<Ws>("127.0.0.1:8000").await?;
+/// The fields of a post stored in the post table; everything else lives in its own table
+#[derive(Debug, Serialize)]
+struct PostRecord<'a> {
+    hdpc_dl_version: i32,
+    program_version: &'a Option<String>,
+    title: &'a str,
+    source_url: &'a str,
+    download_date: &'a str,
+    folder: &'a str,
+    upvotes: Option<u32>,
+    downvotes: Option<u32>,
+    favorites: Option<u32>,
+    views: Option<u32>,
+    comment_count: &'a str,
+    metadata: &'a [InfoboxRowBuf],
+
+    /// The text rows of the metadata, for the full-text search
+    text: String,
+}
+
+impl<'a> From<&'a LibraryPost> for PostRecord<'a> {
+    fn from(post: &'a LibraryPost) -> Self {
+        Self {
+            hdpc_dl_version: post.hdpc_dl_version,
+            program_version: &post.program_version,
+            title: &post.title,
+            source_url: &post.source_url,
+            download_date: &post.download_date,
+            folder: &post.folder,
+            upvotes: post.upvotes,
+            downvotes: post.downvotes,
+            favorites: post.favorites,
+            views: post.views,
+            comment_count: &post.comment_count,
+            metadata: &post.metadata,
+            text: post
+                .metadata
+                .iter()
+                .filter_map(|row| match row {
+                    InfoboxRowBuf::Text {
+                        text: Some(text), ..
+                    } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChapterRecord<'a> {
+    post: &'a Thing,
+    index: usize,
+    name: &'a str,
+    url: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ImageRecord<'a> {
+    post: &'a Thing,
+    index: usize,
+    url: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct CommentRecord<'a> {
+    post: &'a Thing,
+    author: &'a str,
+    content: &'a str,
+    approved: &'a str,
+    likes: i64,
+    posted_on: &'a str,
+    user_id: &'a str,
+    profile_pic: &'a str,
+    role: &'a str,
+}
+
+pub async fn connect(config: &DbConfig) -> anyhow::Result<Db> {
+    info!("Connecting to the database with {config:?}");
 
-    // Signin as a namespace, database, or root user
-    db.signin(Root {
-        username: "root",
-        password: "sKDSm1bHejzy0x38orxYG8sxWZg6wYNAlncEC4HTpWWJ8iOpNf",
-    })
-    .await?;
+    let db = match &config.location {
+        #[cfg(feature = "rocksdb")]
+        DbLocation::RocksDb(path) => {
+            std::fs::create_dir_all(path).context("Failed to create the database folder")?;
+            any::connect(format!("rocksdb://{}", path.to_string_lossy())).await?
+        }
+        DbLocation::Memory(_) => any::connect("mem://").await?,
+        DbLocation::Remote(endpoint) => {
+            let db = any::connect(endpoint.as_str()).await?;
+
+            if let Some(credentials) = &config.credentials {
+                signin(&db, config, credentials)
+                    .await
+                    .with_context(|| format!("Failed to sign in as {}", credentials.username))?;
+            }
+
+            db
+        }
+    };
 
     // Select a specific namespace / database
-    db.use_ns("hdpc-dl").use_db("hdpc-dl").await?;
+    db.use_ns(&config.namespace)
+        .use_db(&config.database)
+        .await?;
+
+    if let DbLocation::Memory(Some(path)) = &config.location {
+        if path.exists() {
+            db.import(path).await.with_context(|| {
+                format!(
+                    "Failed to load the database from {}",
+                    path.to_string_lossy()
+                )
+            })?;
+        }
+    }
+
+    schema::migrate(&db).await?;
 
     Ok(db)
 }
 
-async fn foobar(db: &Surreal<Client>) -> surrealdb::Result<()> {
-    // Create a new person with a random id
-    let created: Vec<Record> = db
-        .create("person")
-        .content(Person {
-            title: "Founder & CEO",
-            name: Name {
-                first: "Tobie",
-                last: "Morgan Hitchcock",
-            },
-            marketing: true,
+/// Signs in as a root, namespace, or database user
+async fn signin(db: &Db, config: &DbConfig, credentials: &Credentials) -> surrealdb::Result<()> {
+    let username = credentials.username.as_str();
+    let password = credentials.password.expose();
+
+    match credentials.level {
+        AuthLevel::Root => db.signin(Root { username, password }).await?,
+        AuthLevel::Namespace => {
+            db.signin(Namespace {
+                namespace: &config.namespace,
+                username,
+                password,
+            })
+            .await?
+        }
+        AuthLevel::Database => {
+            db.signin(Database {
+                namespace: &config.namespace,
+                database: &config.database,
+                username,
+                password,
+            })
+            .await?
+        }
+    };
+
+    Ok(())
+}
+
+/// Saves an in-memory database to its file; other databases persist on their own
+pub async fn disconnect(db: &Db, config: &DbConfig) -> anyhow::Result<()> {
+    if let DbLocation::Memory(Some(path)) = &config.location {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create the database folder")?;
+        }
+
+        db.export(path).await.with_context(|| {
+            format!("Failed to save the database to {}", path.to_string_lossy())
+        })?;
+        info!("Saved the database to {}", path.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// Creates or replaces a taxonomy term and returns the term it replaced
+pub async fn upsert_taxonomy_term(
+    db: &Db,
+    taxonomy: Taxonomy,
+    value: &FilterValue,
+) -> surrealdb::Result<Option<TaxonomyTerm>> {
+    let id = (taxonomy.as_str(), value.id);
+
+    let previous: Option<TaxonomyTerm> = db.select(id).await?;
+    let _: Option<TaxonomyTerm> = db.update(id).content(TaxonomyTerm::from(value)).await?;
+
+    Ok(previous)
+}
+
+/// Moves the posts of terms created from links before the filter lists were fetched (see [`find_or_create_term`])
+/// to the term of the site with the same tag or name, and deletes them; returns how many were merged
+pub async fn merge_placeholder_terms(
+    db: &Db,
+    taxonomy: Taxonomy,
+    values: &[FilterValue],
+) -> surrealdb::Result<usize> {
+    // The terms of the site have numeric ids, the ones created from links their tag
+    let records: Vec<TaxonomyTermRecord> = db.select(taxonomy.as_str()).await?;
+    let placeholders: Vec<_> = records
+        .into_iter()
+        .filter(|record| matches!(record.id.id, Id::String(_)))
+        .collect();
+    if placeholders.is_empty() {
+        return Ok(0);
+    }
+
+    let by_tag: HashMap<&str, u64> = values
+        .iter()
+        .map(|value| (value.tag.as_str(), value.id))
+        .collect();
+    let by_name: HashMap<String, u64> = values
+        .iter()
+        .map(|value| (value.name.to_lowercase(), value.id))
+        .collect();
+
+    let mut merged = 0;
+    for placeholder in placeholders {
+        let Some(id) = by_tag
+            .get(placeholder.tag.as_str())
+            .or_else(|| by_name.get(&placeholder.name.to_lowercase()))
+        else {
+            continue;
+        };
+        let term = Thing::from((taxonomy.as_str(), Id::from(*id)));
+
+        let posts: Vec<Thing> = db
+            .query(
+                "SELECT VALUE in FROM tagged WHERE out = $placeholder
+                    AND in NOTINSIDE (SELECT VALUE in FROM tagged WHERE out = $term)",
+            )
+            .bind(("placeholder", &placeholder.id))
+            .bind(("term", &term))
+            .await?
+            .take(0)?;
+
+        for post in posts {
+            db.query("RELATE $post->tagged->$term")
+                .bind(("post", post))
+                .bind(("term", &term))
+                .await?
+                .check()?;
+        }
+
+        db.query("DELETE tagged WHERE out = $placeholder")
+            .query("DELETE $placeholder")
+            .bind(("placeholder", &placeholder.id))
+            .await?
+            .check()?;
+        info!("Merged the {taxonomy} {} into {term}", placeholder.id);
+        merged += 1;
+    }
+
+    Ok(merged)
+}
+
+/// Loads all stored terms of a taxonomy
+pub async fn taxonomy_terms(db: &Db, taxonomy: Taxonomy) -> surrealdb::Result<Vec<FilterValue>> {
+    let records: Vec<TaxonomyTermRecord> = db.select(taxonomy.as_str()).await?;
+
+    Ok(records
+        .into_iter()
+        .filter_map(|record| match record.id.id {
+            Id::Number(id) => Some(FilterValue {
+                name: record.name,
+                id: id as u64,
+                tag: record.tag,
+            }),
+            _ => None,
         })
+        .collect())
+}
+
+/// The record id of a post: its id, or its URL if the id is unknown (version 4 exports)
+pub fn post_thing(post: &LibraryPost) -> Thing {
+    let id: Id = match post.post_id {
+        Some(post_id) => post_id.into(),
+        None => post.source_url.clone().into(),
+    };
+
+    Thing::from(("post", id))
+}
+
+/// Creates or replaces a post together with its chapters, images, terms, and comments
+pub async fn store_post(db: &Db, post: &LibraryPost) -> anyhow::Result<Thing> {
+    let thing = post_thing(post);
+
+    let _: Option<Record> = db
+        .update((thing.tb.as_str(), thing.id.clone()))
+        .content(PostRecord::from(post))
         .await?;
-    dbg!(created);
 
-    // Update a person record with a specific id
-    let updated: Option<Record> = db
-        .update(("person", "jaime"))
-        .merge(Responsibility { marketing: true })
+    let chapters: Vec<_> = post
+        .chapters
+        .iter()
+        .enumerate()
+        .map(|(index, chapter)| ChapterRecord {
+            post: &thing,
+            index,
+            name: &chapter.name,
+            url: &chapter.url,
+        })
+        .collect();
+
+    let images: Vec<_> = post
+        .picture_urls
+        .iter()
+        .enumerate()
+        .map(|(index, url)| ImageRecord {
+            post: &thing,
+            index,
+            url,
+        })
+        .collect();
+
+    db.query("DELETE chapter WHERE post = $post")
+        .query("DELETE image WHERE post = $post")
+        .query("DELETE tagged WHERE in = $post")
+        .query("INSERT INTO chapter $chapters")
+        .query("INSERT INTO image $images")
+        .bind(("post", &thing))
+        .bind(("chapters", chapters))
+        .bind(("images", images))
+        .await?
+        .check()?;
+
+    // A term may be listed more than once, but can only be related once
+    let mut terms = vec![];
+    for row in post.metadata.iter() {
+        let InfoboxRowBuf::TagLike { tags, .. } = row else {
+            continue;
+        };
+
+        for tag in tags {
+            if let Some((taxonomy, slug)) = term_of_href(&tag.href) {
+                let term = find_or_create_term(db, taxonomy, &slug, &tag.text).await?;
+
+                if !terms.contains(&term) {
+                    terms.push(term);
+                }
+            }
+        }
+    }
+
+    for term in terms {
+        db.query("RELATE $post->tagged->$term")
+            .bind(("post", &thing))
+            .bind(("term", term))
+            .await?
+            .check()?;
+    }
+
+    if let Some(comments) = &post.comments {
+        sync_comments(db, &thing, comments, CommentSync::Import).await?;
+    }
+
+    store_download_stats(db, &thing, post).await?;
+
+    Ok(thing)
+}
+
+/// Stores the stats of an export as observed at its download date
+///
+/// Storing the same export again replaces the observation, so re-imports don't add duplicates.
+async fn store_download_stats(db: &Db, thing: &Thing, post: &LibraryPost) -> anyhow::Result<()> {
+    // Version 4 exports may have dates which can't be charted
+    let Ok(date) = DateTime::parse_from_rfc3339(&post.download_date) else {
+        return Ok(());
+    };
+
+    if [post.views, post.upvotes, post.downvotes, post.favorites]
+        .iter()
+        .all(Option::is_none)
+    {
+        return Ok(());
+    }
+
+    db.query("DELETE stats WHERE post = $post AND source = 'download' AND date = <datetime> $date")
+        .query(
+            "CREATE stats CONTENT {
+                post: $post,
+                date: <datetime> $date,
+                source: 'download',
+                views: $views,
+                upvotes: $upvotes,
+                downvotes: $downvotes,
+                favorites: $favorites,
+            }",
+        )
+        .bind(("post", thing))
+        .bind(("date", date.with_timezone(&Utc).to_rfc3339()))
+        .bind(("views", post.views))
+        .bind(("upvotes", post.upvotes))
+        .bind(("downvotes", post.downvotes))
+        .bind(("favorites", post.favorites))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Gets the taxonomy and the tag from a link like `https://hdporncomics.com/artist/some-artist/`
+pub fn term_of_href(href: &str) -> Option<(Taxonomy, String)> {
+    let url = reqwest::Url::parse(href).ok()?;
+    let mut segments = url.path_segments()?.filter(|segment| !segment.is_empty());
+
+    let taxonomy = segments.next()?.parse().ok()?;
+    let slug = segments.next()?;
+
+    Some((taxonomy, slug.to_lowercase()))
+}
+
+/// Finds a term by its tag, or creates it with the tag as id if the filter lists weren't fetched yet
+async fn find_or_create_term(
+    db: &Db,
+    taxonomy: Taxonomy,
+    tag: &str,
+    name: &str,
+) -> anyhow::Result<Thing> {
+    let found: Option<Thing> = db
+        .query("SELECT VALUE id FROM type::table($table) WHERE tag = $tag LIMIT 1")
+        .bind(("table", taxonomy.as_str()))
+        .bind(("tag", tag))
+        .await?
+        .take(0)?;
+
+    if let Some(thing) = found {
+        return Ok(thing);
+    }
+
+    let created: Option<Record> = db
+        .update((taxonomy.as_str(), tag))
+        .content(TaxonomyTerm {
+            name: name.to_string(),
+            tag: tag.to_string(),
+        })
         .await?;
-    dbg!(updated);
 
-    // Select all people records
-    let people: Vec<Record> = db.select("person").await?;
-    dbg!(people);
+    created
+        .map(|record| record.id)
+        .with_context(|| format!("Failed to create the {taxonomy} {tag}"))
+}
+
+/// How much of the comments of a post a list of comments covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentSync {
+    /// Comments of an export, which may be older than the stored ones, so only unknown comments are added
+    Import,
+
+    /// Some of the current comments: new comments are added and edits are recorded
+    Partial,
+
+    /// All current comments: stored comments which are missing are also marked as deleted
+    Complete,
+}
+
+/// The changes [`sync_comments`] recorded
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CommentChanges {
+    pub added: usize,
+    pub edited: usize,
+    pub deleted: usize,
+    pub restored: usize,
+}
+
+/// A stored comment, as needed to detect changes
+#[derive(Debug, Deserialize)]
+pub struct StoredComment {
+    pub id: String,
+    pub content: String,
+    pub deleted: Option<bool>,
+}
+
+/// Gets the stored comments of a post by their id, including deleted ones
+pub async fn stored_comments(
+    db: &Db,
+    post: &Thing,
+) -> surrealdb::Result<HashMap<String, StoredComment>> {
+    let comments: Vec<StoredComment> = db
+        .query(
+            "SELECT <string> meta::id(id) AS id, content, deleted
+                FROM comment WHERE post = $post",
+        )
+        .bind(("post", post))
+        .await?
+        .take(0)?;
+
+    Ok(comments
+        .into_iter()
+        .map(|comment| (comment.id.clone(), comment))
+        .collect())
+}
+
+/// Stores the comments of a post (including all replies) and records how they changed
+pub async fn sync_comments(
+    db: &Db,
+    post: &Thing,
+    comments: &[Comment],
+    sync: CommentSync,
+) -> anyhow::Result<CommentChanges> {
+    let stored = stored_comments(db, post).await?;
+    let mut changes = CommentChanges::default();
+
+    // The first comments stored for a post aren't changes
+    let record_changes = sync != CommentSync::Import && !stored.is_empty();
+    let mut seen = HashSet::new();
+
+    let mut pending: Vec<&Comment> = comments.iter().collect();
+
+    while let Some(comment) = pending.pop() {
+        if let Some(children) = &comment.children {
+            pending.extend(children.values());
+        }
+
+        // Replies may be listed both nested and flat
+        if !seen.insert(comment.comment_id.as_str()) {
+            continue;
+        }
+
+        let change = match stored.get(&comment.comment_id) {
+            None => Some(("added", None)),
+            Some(_) if sync == CommentSync::Import => continue,
+            Some(previous) if previous.deleted == Some(true) => Some(("restored", None)),
+            Some(previous) if previous.content != comment.content => {
+                Some(("edited", Some(previous.content.as_str())))
+            }
+            // Stored again anyway, as the likes may have changed
+            Some(_) => None,
+        };
+
+        store_comment(db, post, comment).await?;
+
+        if let Some((kind, previous_content)) = change.filter(|_| record_changes) {
+            record_comment_change(db, post, &comment.comment_id, kind, previous_content).await?;
+
+            match kind {
+                "added" => changes.added += 1,
+                "restored" => changes.restored += 1,
+                _ => changes.edited += 1,
+            }
+        }
+    }
+
+    if sync == CommentSync::Complete {
+        for (id, _) in stored
+            .iter()
+            .filter(|(id, comment)| !seen.contains(id.as_str()) && comment.deleted != Some(true))
+        {
+            db.query("UPDATE $comment SET deleted = true")
+                .bind(("comment", Thing::from(("comment", comment_id(id)))))
+                .await?
+                .check()?;
+
+            record_comment_change(db, post, id, "deleted", None).await?;
+            changes.deleted += 1;
+        }
+    }
 
-    // Perform a custom advanced query
-    let groups = db
-        .query("SELECT marketing, count() FROM type::table($table) GROUP BY marketing")
-        .bind(("table", "person"))
+    Ok(changes)
+}
+
+async fn record_comment_change(
+    db: &Db,
+    post: &Thing,
+    id: &str,
+    kind: &str,
+    previous_content: Option<&str>,
+) -> surrealdb::Result<()> {
+    db.query(
+        "CREATE comment_change CONTENT {
+            comment: $comment,
+            post: $post,
+            kind: $kind,
+            date: time::now(),
+            previous_content: $previous_content,
+        }",
+    )
+    .bind(("comment", Thing::from(("comment", comment_id(id)))))
+    .bind(("post", post))
+    .bind(("kind", kind))
+    .bind(("previous_content", previous_content))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Stores one comment and links it to its parent
+async fn store_comment(db: &Db, post: &Thing, comment: &Comment) -> anyhow::Result<()> {
+    let _: Option<Record> = db
+        .update(("comment", comment_id(&comment.comment_id)))
+        .content(CommentRecord {
+            post,
+            author: &comment.comment_author,
+            content: &comment.content,
+            approved: &comment.approved,
+            likes: comment.likes,
+            posted_on: &comment.posted_on,
+            user_id: &comment.user_id,
+            profile_pic: &comment.profile_pic,
+            role: &comment.role,
+        })
         .await?;
-    dbg!(groups);
+
+    // Top-level comments have "0" as parent
+    if comment.comment_parrent != "0" && !comment.comment_parrent.is_empty() {
+        db.query("DELETE replies_to WHERE in = $comment")
+            .query("RELATE $comment->replies_to->$parent")
+            .bind((
+                "comment",
+                Thing::from(("comment", comment_id(&comment.comment_id))),
+            ))
+            .bind((
+                "parent",
+                Thing::from(("comment", comment_id(&comment.comment_parrent))),
+            ))
+            .await?
+            .check()?;
+    }
+
+    Ok(())
+}
+
+/// Gets the posts which have stored comments
+pub async fn posts_with_comments(db: &Db) -> surrealdb::Result<Vec<Thing>> {
+    #[derive(Debug, Deserialize)]
+    struct PostOfComment {
+        post: Thing,
+    }
+
+    let posts: Vec<PostOfComment> = db
+        .query("SELECT post FROM comment GROUP BY post")
+        .await?
+        .take(0)?;
+
+    Ok(posts.into_iter().map(|post| post.post).collect())
+}
+
+/// Comment ids are numeric strings, which are stored as numbers if possible
+fn comment_id(id: &str) -> Id {
+    match id.parse::<u64>() {
+        Ok(id) => id.into(),
+        Err(_) => id.into(),
+    }
+}
+
+/// Records a download of a post
+pub async fn record_download(
+    db: &Db,
+    post: &Thing,
+    destination: &str,
+    json_only: bool,
+    image_count: usize,
+) -> surrealdb::Result<()> {
+    db.query(
+        "CREATE download CONTENT {
+            post: $post,
+            date: time::now(),
+            program_version: $program_version,
+            destination: $destination,
+            json_only: $json_only,
+            image_count: $image_count,
+        }",
+    )
+    .bind(("post", post))
+    .bind(("program_version", constants::VERSION))
+    .bind(("destination", destination))
+    .bind(("json_only", json_only))
+    .bind(("image_count", image_count))
+    .await?
+    .check()?;
 
     Ok(())
 }
+
+/// The record id of a crawled post: its id, or its URL if the id couldn't be parsed, like [`post_thing`]
+fn crawled_post_thing(post: &PostBuf) -> Thing {
+    let id: Id = match post.post_id {
+        0 => post.url.clone().into(),
+        post_id => u64::from(post_id).into(),
+    };
+
+    Thing::from(("post", id))
+}
+
+/// Records a crawl and the stats of every post found, without touching their other fields
+pub async fn record_crawl(db: &Db, source_url: &str, posts: &[PostBuf]) -> anyhow::Result<()> {
+    let created: Vec<Record> = db
+        .query(
+            "CREATE crawl CONTENT {
+                source_url: $source_url,
+                date: time::now(),
+                program_version: $program_version,
+                post_count: $post_count,
+            }",
+        )
+        .bind(("source_url", source_url))
+        .bind(("program_version", constants::VERSION))
+        .bind(("post_count", posts.len()))
+        .await?
+        .take(0)?;
+    let crawl = created
+        .into_iter()
+        .next()
+        .map(|record| record.id)
+        .context("Failed to create the crawl")?;
+
+    for (position, post) in posts.iter().enumerate() {
+        db.query(
+            "UPDATE $post MERGE {
+                title: $title,
+                source_url: $source_url,
+                views: $views,
+                upvotes: $upvotes,
+                downvotes: $downvotes,
+            }",
+        )
+        .query("RELATE $crawl->found->$post SET position = $position")
+        .query(
+            "CREATE stats CONTENT {
+                post: $post,
+                date: time::now(),
+                source: 'crawl',
+                views: $views,
+                upvotes: $upvotes,
+                downvotes: $downvotes,
+            }",
+        )
+        .bind(("post", crawled_post_thing(post)))
+        .bind(("title", &post.name))
+        .bind(("source_url", &post.url))
+        .bind(("views", post.views))
+        .bind(("upvotes", post.upvotes))
+        .bind(("downvotes", post.downvotes))
+        .bind(("crawl", &crawl))
+        .bind(("position", position))
+        .await?
+        .check()?;
+    }
+
+    Ok(())
+}
+
+/// An observation of the stats of a post, as read back for charts and rankings
+#[derive(Debug, Deserialize)]
+pub struct StatsSnapshot {
+    pub post: Thing,
+    pub title: Option<String>,
+    pub date: DateTime<Utc>,
+    pub views: Option<u32>,
+    pub upvotes: Option<u32>,
+    pub downvotes: Option<u32>,
+    pub favorites: Option<u32>,
+}
+
+const STATS_FIELDS: &str =
+    "post, post.title AS title, <string> date AS date, views, upvotes, downvotes, favorites";
+
+/// Finds a post by its id or URL
+pub async fn find_post(db: &Db, post: &str) -> anyhow::Result<Thing> {
+    if let Ok(post_id) = post.parse::<u64>() {
+        return Ok(Thing::from(("post", Id::from(post_id))));
+    }
+
+    let found: Option<Thing> = db
+        .query("SELECT VALUE id FROM post WHERE source_url = $url LIMIT 1")
+        .bind(("url", post))
+        .await?
+        .take(0)?;
+
+    found.with_context(|| format!("No post with the id or URL {post} is stored"))
+}
+
+/// Gets all stats of a post, oldest first
+pub async fn stats_history(db: &Db, post: &Thing) -> surrealdb::Result<Vec<StatsSnapshot>> {
+    db.query(format!(
+        "SELECT {STATS_FIELDS} FROM stats WHERE post = $post ORDER BY date"
+    ))
+    .bind(("post", post))
+    .await?
+    .take(0)
+}
+
+/// Gets the stats of all posts observed since `since`, oldest first
+pub async fn stats_since(db: &Db, since: DateTime<Utc>) -> surrealdb::Result<Vec<StatsSnapshot>> {
+    db.query(format!(
+        "SELECT {STATS_FIELDS} FROM stats WHERE date >= <datetime> $since ORDER BY date"
+    ))
+    .bind(("since", since.to_rfc3339()))
+    .await?
+    .take(0)
+}
+
+/// A downloaded post as listed by the library queries
+#[derive(Debug, Deserialize)]
+pub struct StoredPost {
+    pub id: Thing,
+    pub title: String,
+    pub source_url: String,
+    pub download_date: String,
+    pub folder: String,
+    pub views: Option<u32>,
+    pub upvotes: Option<u32>,
+    pub downvotes: Option<u32>,
+    pub favorites: Option<u32>,
+}
+
+/// A term linked to a post
+#[derive(Debug, Deserialize)]
+pub struct StoredPostTerm {
+    pub post: Thing,
+    pub taxonomy: Taxonomy,
+    pub name: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PageCount {
+    post: Thing,
+    pages: usize,
+}
+
+/// Gets all downloaded posts (crawled posts have no folder)
+pub async fn downloaded_posts(db: &Db) -> surrealdb::Result<Vec<StoredPost>> {
+    db.query(
+        "SELECT id, title, source_url, download_date, folder, views, upvotes, downvotes, favorites
+            FROM post WHERE folder != NONE",
+    )
+    .await?
+    .take(0)
+}
+
+/// Gets the terms of all posts
+pub async fn post_terms(db: &Db) -> surrealdb::Result<Vec<StoredPostTerm>> {
+    db.query(
+        "SELECT in AS post, meta::tb(out) AS taxonomy, out.name AS name, out.tag AS tag FROM tagged",
+    )
+    .await?
+    .take(0)
+}
+
+/// Gets the number of images of all posts, by the string form of their record id
+pub async fn page_counts(db: &Db) -> surrealdb::Result<HashMap<String, usize>> {
+    let counts: Vec<PageCount> = db
+        .query("SELECT post, count() AS pages FROM image GROUP BY post")
+        .await?
+        .take(0)?;
+
+    Ok(counts
+        .into_iter()
+        .map(|count| (count.post.to_string(), count.pages))
+        .collect())
+}
+
+/// Where a full-text search found a post
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchField {
+    Title,
+    Text,
+    Comment,
+}
+
+impl SearchField {
+    pub const ALL: [SearchField; 3] = [SearchField::Title, SearchField::Text, SearchField::Comment];
+
+    /// Matches in titles count more than matches in the infobox or comments
+    pub fn weight(self) -> f64 {
+        match self {
+            SearchField::Title => 3.,
+            SearchField::Text | SearchField::Comment => 1.,
+        }
+    }
+}
+
+/// A post or comment matching a full-text search
+#[derive(Debug, Deserialize)]
+pub struct SearchHit {
+    pub post: Thing,
+    pub title: Option<String>,
+    pub author: Option<String>,
+
+    /// The matching text, which may contain HTML for comments
+    pub text: String,
+
+    /// The BM25 score of the match
+    pub score: f64,
+}
+
+/// Searches the posts or comments containing all `words`, best matches first
+pub async fn search_text(
+    db: &Db,
+    field: SearchField,
+    words: &str,
+) -> surrealdb::Result<Vec<SearchHit>> {
+    let query = match field {
+        SearchField::Title => {
+            "SELECT id AS post, title, title AS text, search::score(0) AS score
+                FROM post WHERE title @0@ $words ORDER BY score DESC"
+        }
+        SearchField::Text => {
+            "SELECT id AS post, title, text, search::score(0) AS score
+                FROM post WHERE text @0@ $words ORDER BY score DESC"
+        }
+        SearchField::Comment => {
+            "SELECT post, post.title AS title, author, content AS text, search::score(0) AS score
+                FROM comment WHERE content @0@ $words AND deleted != true ORDER BY score DESC"
+        }
+    };
+
+    db.query(query).bind(("words", words)).await?.take(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data::TagLikeBuf, test_utils::memory_db};
+
+    fn tag(href: &str, text: &str) -> TagLikeBuf {
+        TagLikeBuf {
+            href: href.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    fn post(metadata: Vec<InfoboxRowBuf>) -> LibraryPost {
+        LibraryPost {
+            hdpc_dl_version: 7,
+            program_version: Some(constants::VERSION.to_string()),
+            post_id: Some(1234),
+            title: "Title".to_string(),
+            source_url: "https://hdporncomics.com/title/".to_string(),
+            download_date: "2024-01-31T00:00:00Z".to_string(),
+            folder: "title".to_string(),
+            upvotes: None,
+            downvotes: None,
+            favorites: None,
+            views: None,
+            comment_count: "0".to_string(),
+            metadata,
+            chapters: vec![],
+            picture_urls: vec![],
+            comments: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn relates_repeated_terms_once() {
+        let db = memory_db().await;
+        let post = post(vec![
+            InfoboxRowBuf::TagLike {
+                name: "Artist".to_string(),
+                tags: vec![
+                    tag(
+                        "https://hdporncomics.com/artist/some-artist/",
+                        "Some Artist",
+                    ),
+                    tag(
+                        "https://hdporncomics.com/artist/some-artist/",
+                        "Some Artist",
+                    ),
+                ],
+            },
+            InfoboxRowBuf::TagLike {
+                name: "Tags".to_string(),
+                tags: vec![
+                    tag("https://hdporncomics.com/tags/glasses/", "Glasses"),
+                    tag(
+                        "https://hdporncomics.com/artist/Some-Artist/",
+                        "Some Artist",
+                    ),
+                ],
+            },
+        ]);
+
+        store_post(&db, &post).await.unwrap();
+        // Storing a post again replaces its terms
+        store_post(&db, &post).await.unwrap();
+
+        let mut terms: Vec<_> = post_terms(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|term| (term.taxonomy, term.tag))
+            .collect();
+        terms.sort();
+
+        assert_eq!(
+            terms,
+            [
+                (Taxonomy::Artist, "some-artist".to_string()),
+                (Taxonomy::Tags, "glasses".to_string()),
+            ]
+        );
+    }
+}
@@ -1,30 +1,37 @@
-use std::{thread, time::Duration};
+use std::{path::Path, thread, time::Duration};
 
-#[cfg(feature = "python_ffi")]
-use crate::bypass::http_get_bypassed;
 use crate::{
+    comments::{print_media_archive, write_comment_exports, CommentOptions},
     constants,
     data::*,
+    db::{self, CommentSync, Db},
+    fetcher::Fetcher,
+    filters::{FilterValue, Taxonomy},
+    library,
     parser::{
         self, extract_chapters, extract_comment_count, extract_from_infobox_row,
         extract_image_urls, extract_info_box_rows, extract_post_id, extract_res_page_links,
         extract_target_links, extract_title, get_api_view,
     },
+    search::is_excluded,
 };
 use anyhow::Context;
 use chrono::prelude::*;
+use std::sync::Arc;
 use tokio::{fs, io::AsyncWriteExt};
 use tracing::info;
 
 /// Downloads comic(s) from given URL(s) to a target directory
+#[allow(clippy::too_many_arguments)]
 pub async fn download_from_urls(
+    db: &Db,
+    fetcher: &Arc<dyn Fetcher>,
     urls: Vec<String>,
     dest: String,
     verbosity: u64,
     json_only: bool,
     use_padding: bool,
-    #[cfg(feature = "python_ffi")] use_python_bypass: bool,
-    get_comments: bool,
+    comments: Option<&CommentOptions>,
 ) -> Result<(), anyhow::Error> {
     info!("Downloading pre-defined list of URLs");
 
@@ -32,14 +39,14 @@ pub async fn download_from_urls(
     for (n, url) in urls.iter().enumerate().map(|(n, url)| (n + 1, url)) {
         println!("Download {n:02}/{max:02}");
         download_from_url(
+            db,
+            fetcher,
             url.to_string(),
             dest.clone(),
             verbosity,
             json_only,
             use_padding,
-            #[cfg(feature = "python_ffi")]
-            use_python_bypass,
-            get_comments,
+            comments,
         )
         .await?;
     }
@@ -48,14 +55,16 @@ pub async fn download_from_urls(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn download_from_url(
+    db: &Db,
+    fetcher: &Arc<dyn Fetcher>,
     url: String,
     dest: String,
     verbosity: u64,
     json_only: bool,
     use_padding: bool,
-    #[cfg(feature = "python_ffi")] use_python_bypass: bool,
-    get_comments: bool,
+    comments: Option<&CommentOptions>,
 ) -> Result<(), anyhow::Error> {
     info!("Getting target {url}");
 
@@ -65,39 +74,10 @@ pub async fn download_from_url(
     println!("{padding}Destination: {dest}");
     println!("{padding}URL: {url}");
 
-    #[cfg(feature = "python_ffi")]
-    if use_python_bypass {
-        pyo3::prepare_freethreaded_python();
-        info!("Prepared the Python FFI");
-    }
-
-    // Create a client to make requests with
-    let client = reqwest::Client::new();
+    let client = fetcher.client();
 
     // Request the HTML file from the server
-    #[cfg(feature = "python_ffi")]
-    let text = if use_python_bypass {
-        http_get_bypassed(url.clone())?
-    } else {
-        info!("Downloading HTML with Reqwest from {url}",);
-        client
-            .get(url.clone())
-            .send()
-            .await?
-            .text()
-            .await?
-            .to_string()
-    };
-
-    info!("Downloading HTML with Reqwest from {url}",);
-    #[cfg(not(feature = "python_ffi"))]
-    let text = client
-        .get(url.clone())
-        .send()
-        .await?
-        .text()
-        .await?
-        .to_string();
+    let text = fetcher.get_page(&url).await?;
 
     // The URLs of the pictures to be downloaded
     let picture_urls = extract_image_urls(&text);
@@ -110,7 +90,7 @@ pub async fn download_from_url(
     let title = extract_title(&text).context("Couldn't extract title")?;
 
     // Get the stats from the API (upvotes, downvotes, views, and favorites)
-    let api_stats = get_api_view(&client, &url).await?;
+    let api_stats = get_api_view(fetcher.as_ref(), &url).await?;
 
     let comment_count = extract_comment_count(&text).context("Couldn't extract comment count")?;
 
@@ -124,8 +104,9 @@ pub async fn download_from_url(
         .map(extract_from_infobox_row)
         .collect();
 
-    let comments = if get_comments {
-        let comments = parser::get_comments(post_id, &client).await?;
+    let comment_options = comments;
+    let comments = if let Some(options) = comment_options {
+        let comments = parser::get_comments(post_id, fetcher, options.concurrency).await?;
         println!("{padding}Got {} comments", comments.len());
         Some(comments)
     } else {
@@ -134,8 +115,8 @@ pub async fn download_from_url(
     };
 
     // Fill the data structure for the JSON document to be exported
-    let data = ExportV7 {
-        hdpc_dl_version: 7,
+    let data = ExportV8 {
+        hdpc_dl_version: constants::EXPORT_VERSION,
         program_version: constants::VERSION,
         post_id,
         title: &title,
@@ -161,17 +142,57 @@ pub async fn download_from_url(
         .context("Failed to create directory.\nTry to specify another path.\n")?;
 
     // The JSON path
-    let json_path = path.clone() + "/hdpc-info.json";
+    let json_path = path.clone() + "/" + constants::EXPORT_FILE_NAME;
 
     // Write the JSON file to disk
-    std::fs::write(&json_path, serialized)
+    std::fs::write(&json_path, &serialized)
         .context("Failed to create the JSON file.\nTry to specify another path.\n")?;
 
     // Log successful JSON file creation
     println!("{padding}Created JSON file at \"{}\"", &json_path);
 
+    if let (Some(options), Some(comments)) = (comment_options, &data.comments) {
+        let exports = write_comment_exports(
+            Some(fetcher.as_ref()),
+            Path::new(&path),
+            &title,
+            &url,
+            comments,
+            options,
+        )
+        .await?;
+
+        for path in exports.files {
+            println!("{padding}Created comment export at \"{path}\"");
+        }
+
+        if let Some(media) = exports.media {
+            print_media_archive(&format!("{padding}Comments"), &media);
+        }
+    }
+
+    // Store the post the same way an import of its export would
+    let export: ExportV8Buf = serde_json::from_str(&serialized)?;
+    let library_post = library::upgrade_v8(export, path.clone());
+
+    // Unlike imports, these are all current comments, so their changes are recorded first
+    if let Some(comments) = &library_post.comments {
+        let thing = db::post_thing(&library_post);
+        let changes = db::sync_comments(db, &thing, comments, CommentSync::Complete).await?;
+
+        if changes.edited + changes.deleted + changes.restored > 0 {
+            println!(
+                "{padding}Comments changed since the last download: {} added, {} edited, {} deleted, {} restored",
+                changes.added, changes.edited, changes.deleted, changes.restored
+            );
+        }
+    }
+
+    let post = db::store_post(db, &library_post).await?;
+
     // Return if --json-only was specified
     if json_only {
+        db::record_download(db, &post, &dest, true, 0).await?;
         return Ok(());
     }
 
@@ -243,6 +264,8 @@ pub async fn download_from_url(
         };
     }
 
+    db::record_download(db, &post, &dest, false, picture_urls.len()).await?;
+
     println!(
         "{pad}Successfully downloaded all {count} images from \"{title}\".",
         count = picture_urls.len(),
@@ -253,8 +276,10 @@ pub async fn download_from_url(
     Ok(())
 }
 
-/// Crawls an entire search results page and downloads everything
+/// Crawls an entire search results page and downloads everything but the posts with an excluded term
 pub async fn crawl_download(
+    db: &Db,
+    fetcher: &Arc<dyn Fetcher>,
     url: &str,
     dest: &str,
     verbosity: u64,
@@ -264,23 +289,10 @@ pub async fn crawl_download(
     paging: bool,
     max_retries: usize,
     no_download: bool,
-    #[cfg(feature = "python_ffi")] use_python_bypass: bool,
-    get_comments: bool,
+    comments: Option<&CommentOptions>,
+    exclude: &[(Taxonomy, FilterValue)],
 ) -> Result<(), anyhow::Error> {
-    // Create a client to make requests with
-    #[cfg(not(feature = "python_ffi"))]
-    let client = reqwest::Client::new();
-
-    #[cfg(feature = "python_ffi")]
-    let text = http_get_bypassed(url)?;
-    #[cfg(not(feature = "python_ffi"))]
-    let text = client
-        .get(url.clone())
-        .send()
-        .await?
-        .text()
-        .await?
-        .to_string();
+    let text = fetcher.get_page(url).await?;
 
     let res_pages = {
         let mut res_pages = if paging {
@@ -313,16 +325,7 @@ pub async fn crawl_download(
         // TODO implement skip properly
 
         // Collect all URLs to download
-        #[cfg(feature = "python_ffi")]
-        let text = http_get_bypassed(page.url)?;
-        #[cfg(not(feature = "python_ffi"))]
-        let text = client
-            .get(page.url.clone())
-            .send()
-            .await?
-            .text()
-            .await?
-            .to_string();
+        let text = fetcher.get_page(page.url).await?;
         let mut page_contents = extract_target_links(text);
         println!(
             "Collected {post_count: >2} posts from page {current_page: >4}; {total: >4} in total",
@@ -334,8 +337,17 @@ pub async fn crawl_download(
         // thread::sleep(Duration::from_secs(3));
     }
 
-    let export = CrawlResultV5 {
-        hdpc_dl_version: 6,
+    if !exclude.is_empty() {
+        let found = targets.len();
+        targets.retain(|post| !is_excluded(post, exclude));
+        println!(
+            "Excluded {excluded} posts with an excluded term",
+            excluded = found - targets.len()
+        );
+    }
+
+    let export = CrawlResultV6 {
+        hdpc_dl_version: constants::CRAWL_RESULT_VERSION,
         program_version: constants::VERSION,
         source_url: url,
         download_date: Utc::now().to_rfc3339(),
@@ -356,7 +368,7 @@ pub async fn crawl_download(
     let crawl_export_name = Utc::now().to_rfc3339().replace(" ", "_");
 
     // The JSON path
-    let json_path = path.clone() + "/" + &crawl_export_name + "_crawl_results.json";
+    let json_path = path.clone() + "/" + &crawl_export_name + constants::CRAWL_RESULTS_SUFFIX;
 
     // Write the JSON file to disk
     std::fs::write(&json_path, serialized)
@@ -366,6 +378,8 @@ pub async fn crawl_download(
     println!("Created JSON file with crawl results at \"{}\"", &json_path);
     println!("from path {url}");
 
+    db::record_crawl(db, url, &targets).await?;
+
     if no_download {
         return Ok(());
     }
@@ -392,14 +406,14 @@ pub async fn crawl_download(
 
         // Download the target
         while let Err(e) = download_from_url(
+            db,
+            fetcher,
             target.url.clone(),
             dest.to_owned(),
             verbosity,
             json_only,
             true,
-            #[cfg(feature = "python_ffi")]
-            use_python_bypass,
-            get_comments,
+            comments,
         )
         .await
         {
@@ -0,0 +1,457 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::{cookie::Jar, Client, Url};
+use serde::Deserialize;
+use tracing::info;
+
+#[cfg(feature = "python_ffi")]
+use crate::bypass::Scraper;
+#[cfg(feature = "python_ffi")]
+use crate::http::Proxies;
+use crate::{command_fetcher::CommandFetcher, http::HttpSettings};
+
+/// Makes the HTTP requests of a run
+///
+/// Pages may come from elsewhere, e.g. a scraping bypass, and everything but the images of posts may be
+/// recorded and replayed; by default, requests are sent with [`Fetcher::client`].
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    /// Gets the HTML of a page
+    async fn get_page(&self, url: &str) -> anyhow::Result<String>;
+
+    /// Gets a response of the API, which selects some posts by the `Referer` header
+    async fn get_api(&self, url: &str, referer: Option<&str>) -> anyhow::Result<String> {
+        get_text(self.client(), url, referer).await
+    }
+
+    /// Posts a form to the API, e.g. to get a filter list
+    async fn post_form(&self, url: &str, form: &[(&str, &str)]) -> anyhow::Result<String> {
+        Ok(self
+            .client()
+            .post(url)
+            .form(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?)
+    }
+
+    /// Gets a small file, e.g. comment media
+    async fn get_file(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let res = self.client().get(url).send().await?.error_for_status()?;
+        Ok(res.bytes().await?.to_vec())
+    }
+
+    /// The client for the images of posts, which are streamed to disk
+    fn client(&self) -> &Client;
+}
+
+/// Gets a text with reqwest, failing on error statuses
+async fn get_text(client: &Client, url: &str, referer: Option<&str>) -> anyhow::Result<String> {
+    let mut req = client.get(url);
+    if let Some(referer) = referer {
+        req = req.header("Referer", referer);
+    }
+
+    Ok(req.send().await?.error_for_status()?.text().await?)
+}
+
+/// A cookie of a bypass session, e.g. the clearance cookie of a solved challenge
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionCookie {
+    pub name: String,
+    #[serde(default)]
+    pub value: String,
+
+    /// Empty for cookies which only belong to the host they came from
+    #[serde(default)]
+    pub domain: String,
+    #[serde(default = "crate::http::root_path")]
+    pub path: String,
+}
+
+/// Adds the cookies of a bypass session, which requested `url`, to a cookie jar
+pub fn add_session_cookies(
+    jar: &Jar,
+    url: &str,
+    cookies: Vec<SessionCookie>,
+) -> anyhow::Result<()> {
+    for cookie in cookies {
+        // Host-only cookies belong to the requested host
+        let (origin, domain) = match cookie.domain.trim_start_matches('.') {
+            "" => (Url::parse(url)?, String::new()),
+            domain => (
+                Url::parse(&format!("https://{domain}/"))?,
+                format!("; Domain={domain}"),
+            ),
+        };
+
+        jar.add_cookie_str(
+            &format!(
+                "{}={}; Path={}{domain}",
+                cookie.name, cookie.value, cookie.path
+            ),
+            &origin,
+        );
+    }
+
+    Ok(())
+}
+
+/// A short name for a URL which stays the same between runs (64-bit FNV-1a)
+pub fn url_hash(url: &str) -> String {
+    let hash = url.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
+}
+
+/// Requests everything with reqwest
+pub struct PlainFetcher {
+    client: Client,
+}
+
+impl PlainFetcher {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl Fetcher for PlainFetcher {
+    async fn get_page(&self, url: &str) -> anyhow::Result<String> {
+        info!("Downloading HTML with Reqwest from {url}");
+        get_text(&self.client, url, None).await
+    }
+
+    fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+/// Requests pages with the Python `cloudscraper` library to get past scraping prevention
+///
+/// The cookies of solved challenges are passed on to the client, which also sends the user agent of the session,
+/// so API and image requests pass as well. Pages go through the same proxies as the client.
+#[cfg(feature = "python_ffi")]
+pub struct BypassFetcher {
+    scraper: Arc<Scraper>,
+    jar: Arc<Jar>,
+    client: Client,
+    proxies: Proxies,
+}
+
+#[cfg(feature = "python_ffi")]
+impl BypassFetcher {
+    pub fn new(settings: &HttpSettings, jar: Arc<Jar>) -> anyhow::Result<Self> {
+        let scraper = Scraper::new(settings.user_agent.as_deref())
+            .context("Starting the cloudscraper session failed")?;
+        let client = settings
+            .client_builder(jar.clone())?
+            .user_agent(scraper.user_agent())
+            .build()?;
+
+        Ok(Self {
+            scraper: Arc::new(scraper),
+            jar,
+            client,
+            proxies: settings.proxies.clone(),
+        })
+    }
+}
+
+#[cfg(feature = "python_ffi")]
+#[async_trait]
+impl Fetcher for BypassFetcher {
+    async fn get_page(&self, url: &str) -> anyhow::Result<String> {
+        let scraper = self.scraper.clone();
+        let owned_url = url.to_string();
+        let proxy = self.proxies.select(&Url::parse(url)?);
+        let page = tokio::task::spawn_blocking(move || {
+            scraper.get(&owned_url, proxy.as_ref().map(Url::as_str))
+        })
+        .await??;
+
+        add_session_cookies(&self.jar, url, page.cookies)?;
+
+        Ok(page.text)
+    }
+
+    fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+/// The file a response is recorded to
+fn recording_path(dir: &Path, url: &str, referer: Option<&str>) -> PathBuf {
+    match referer {
+        Some(referer) => dir.join(format!("{}.json", url_hash(&format!("{url} {referer}")))),
+        None => dir.join(format!("{}.html", url_hash(url))),
+    }
+}
+
+/// The file the response to a posted form is recorded to
+fn form_recording_path(dir: &Path, url: &str, form: &[(&str, &str)]) -> PathBuf {
+    let body = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(form)
+        .finish();
+
+    dir.join(format!("{}.json", url_hash(&format!("POST {url} {body}"))))
+}
+
+/// The file a downloaded file is recorded to
+fn file_recording_path(dir: &Path, url: &str) -> PathBuf {
+    dir.join(format!("{}.file", url_hash(url)))
+}
+
+/// Reads a recording, failing with the URL it was expected for
+async fn replay(path: &Path, url: &str) -> anyhow::Result<Vec<u8>> {
+    tokio::fs::read(path).await.with_context(|| {
+        format!(
+            "{url} wasn't recorded (expected \"{}\")",
+            path.to_string_lossy()
+        )
+    })
+}
+
+/// Saves every page and API response of another fetcher, so later runs can replay them with [`OfflineFetcher`]
+pub struct RecordingFetcher {
+    inner: Box<dyn Fetcher>,
+    dir: PathBuf,
+}
+
+impl RecordingFetcher {
+    pub fn new(inner: Box<dyn Fetcher>, dir: PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir).with_context(|| {
+            format!(
+                "Failed to create the recordings folder \"{}\"",
+                dir.to_string_lossy()
+            )
+        })?;
+
+        Ok(Self { inner, dir })
+    }
+
+    async fn record(&self, url: &str, path: PathBuf, contents: &[u8]) -> anyhow::Result<()> {
+        info!("Recording {url} to {}", path.to_string_lossy());
+
+        tokio::fs::write(&path, contents)
+            .await
+            .with_context(|| format!("Failed to record {url} to \"{}\"", path.to_string_lossy()))
+    }
+}
+
+#[async_trait]
+impl Fetcher for RecordingFetcher {
+    async fn get_page(&self, url: &str) -> anyhow::Result<String> {
+        let text = self.inner.get_page(url).await?;
+        let path = recording_path(&self.dir, url, None);
+        self.record(url, path, text.as_bytes()).await?;
+
+        Ok(text)
+    }
+
+    async fn get_api(&self, url: &str, referer: Option<&str>) -> anyhow::Result<String> {
+        let text = self.inner.get_api(url, referer).await?;
+        let path = recording_path(&self.dir, url, referer);
+        self.record(url, path, text.as_bytes()).await?;
+
+        Ok(text)
+    }
+
+    async fn post_form(&self, url: &str, form: &[(&str, &str)]) -> anyhow::Result<String> {
+        let text = self.inner.post_form(url, form).await?;
+        let path = form_recording_path(&self.dir, url, form);
+        self.record(url, path, text.as_bytes()).await?;
+
+        Ok(text)
+    }
+
+    async fn get_file(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let bytes = self.inner.get_file(url).await?;
+        self.record(url, file_recording_path(&self.dir, url), &bytes)
+            .await?;
+
+        Ok(bytes)
+    }
+
+    fn client(&self) -> &Client {
+        self.inner.client()
+    }
+}
+
+/// Replays the pages, API responses, and files saved by [`RecordingFetcher`] without touching the network
+///
+/// The images of posts are still requested with the client, so this is mostly useful with `--json-only` or crawls.
+pub struct OfflineFetcher {
+    client: Client,
+    dir: PathBuf,
+}
+
+impl OfflineFetcher {
+    pub fn new(client: Client, dir: PathBuf) -> Self {
+        Self { client, dir }
+    }
+
+    async fn replay_text(&self, url: &str, path: PathBuf) -> anyhow::Result<String> {
+        let bytes = replay(&path, url).await?;
+
+        String::from_utf8(bytes).with_context(|| format!("The recording of {url} isn't text"))
+    }
+}
+
+#[async_trait]
+impl Fetcher for OfflineFetcher {
+    async fn get_page(&self, url: &str) -> anyhow::Result<String> {
+        self.replay_text(url, recording_path(&self.dir, url, None))
+            .await
+    }
+
+    async fn get_api(&self, url: &str, referer: Option<&str>) -> anyhow::Result<String> {
+        self.replay_text(url, recording_path(&self.dir, url, referer))
+            .await
+    }
+
+    async fn post_form(&self, url: &str, form: &[(&str, &str)]) -> anyhow::Result<String> {
+        self.replay_text(url, form_recording_path(&self.dir, url, form))
+            .await
+    }
+
+    async fn get_file(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        replay(&file_recording_path(&self.dir, url), url).await
+    }
+
+    fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+/// Which [`Fetcher`] gets the pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetcherKind {
+    Plain,
+    Bypass,
+    Command,
+    Offline,
+}
+
+impl FetcherKind {
+    pub const NAMES: [&'static str; 4] = ["plain", "bypass", "command", "offline"];
+}
+
+impl FromStr for FetcherKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "plain" => Ok(FetcherKind::Plain),
+            "bypass" => Ok(FetcherKind::Bypass),
+            "command" => Ok(FetcherKind::Command),
+            "offline" => Ok(FetcherKind::Offline),
+            _ => anyhow::bail!(
+                "Unknown fetcher {s}; expected one of {}",
+                FetcherKind::NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+/// Creates the fetcher of a run
+///
+/// The offline fetcher replays `recordings`; the others record to it if it's set.
+/// The command fetcher runs `command`. All clients are created with `settings`.
+pub fn create_fetcher(
+    kind: FetcherKind,
+    recordings: Option<PathBuf>,
+    command: Option<&str>,
+    settings: &HttpSettings,
+) -> anyhow::Result<Arc<dyn Fetcher>> {
+    let jar = settings.cookie_jar()?;
+    let client = || {
+        settings
+            .client_builder(jar.clone())?
+            .build()
+            .map_err(anyhow::Error::from)
+    };
+
+    let fetcher: Box<dyn Fetcher> = match kind {
+        FetcherKind::Plain => Box::new(PlainFetcher::new(client()?)),
+        #[cfg(feature = "python_ffi")]
+        FetcherKind::Bypass => Box::new(BypassFetcher::new(settings, jar)?),
+        #[cfg(not(feature = "python_ffi"))]
+        FetcherKind::Bypass => anyhow::bail!(
+            "The bypass fetcher needs the python_ffi feature, which this program was built without; try --fetcher command"
+        ),
+        FetcherKind::Command => Box::new(CommandFetcher::spawn(
+            command.context("The command fetcher needs --bypass-command")?,
+            settings.clone(),
+            jar,
+        )?),
+        FetcherKind::Offline => {
+            let dir = recordings.context("The offline fetcher needs --recordings")?;
+            return Ok(Arc::new(OfflineFetcher::new(client()?, dir)));
+        }
+    };
+
+    Ok(match recordings {
+        Some(dir) => Arc::new(RecordingFetcher::new(fetcher, dir)?),
+        None => Arc::from(fetcher),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{temp_dir, StubFetcher};
+
+    #[tokio::test]
+    async fn replays_recorded_requests() {
+        let dir = temp_dir("recordings");
+        let page = "https://example.com/post/1";
+        let api = "https://example.com/api";
+        let file = "https://example.com/image.png";
+
+        let mut stub = StubFetcher::default();
+        stub.pages
+            .insert(page.to_string(), "<html>post</html>".to_string());
+        stub.api
+            .insert(format!("{api} {page}"), r#"{"posts":[]}"#.to_string());
+        stub.files.insert(file.to_string(), vec![0, 159, 146, 150]);
+
+        let recorder = RecordingFetcher::new(Box::new(stub), dir.clone()).unwrap();
+        recorder.get_page(page).await.unwrap();
+        recorder.get_api(api, Some(page)).await.unwrap();
+        recorder.get_file(file).await.unwrap();
+
+        assert!(dir.join(format!("{}.html", url_hash(page))).is_file());
+        assert!(dir
+            .join(format!("{}.json", url_hash(&format!("{api} {page}"))))
+            .is_file());
+        assert!(dir.join(format!("{}.file", url_hash(file))).is_file());
+
+        let offline = OfflineFetcher::new(Client::new(), dir.clone());
+        assert_eq!(offline.get_page(page).await.unwrap(), "<html>post</html>");
+        assert_eq!(
+            offline.get_api(api, Some(page)).await.unwrap(),
+            r#"{"posts":[]}"#
+        );
+        assert_eq!(
+            offline.get_file(file).await.unwrap(),
+            vec![0, 159, 146, 150]
+        );
+
+        // The same URL with another referer selects other posts, so it's another recording
+        let error = offline
+            .get_api(api, Some("https://example.com/post/2"))
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("wasn't recorded"));
+    }
+}
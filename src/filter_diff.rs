@@ -0,0 +1,228 @@
+use std::{collections::BTreeMap, fs::read_to_string, path::PathBuf};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::filters::{FilterValue, FiltersExportV1, Taxonomy};
+
+/// A term whose name or tag changed between two snapshots
+#[derive(Debug, Serialize)]
+pub struct RenamedTerm {
+    pub id: u64,
+    pub old_name: String,
+    pub new_name: String,
+    pub old_tag: String,
+    pub new_tag: String,
+}
+
+/// The changes of one taxonomy, with terms matched by id
+#[derive(Debug, Default, Serialize)]
+pub struct TaxonomyDiff {
+    pub added: Vec<FilterValue>,
+    pub removed: Vec<FilterValue>,
+    pub renamed: Vec<RenamedTerm>,
+}
+
+impl TaxonomyDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty()
+    }
+}
+
+/// The changes between two filter snapshots
+#[derive(Debug, Serialize)]
+pub struct FiltersDiff {
+    pub old_download_date: String,
+    pub new_download_date: String,
+    pub taxonomies: BTreeMap<Taxonomy, TaxonomyDiff>,
+}
+
+/// Compares the terms of one taxonomy
+pub fn diff_taxonomy(old: &[FilterValue], new: &[FilterValue]) -> TaxonomyDiff {
+    let old: BTreeMap<u64, &FilterValue> = old.iter().map(|value| (value.id, value)).collect();
+    let new: BTreeMap<u64, &FilterValue> = new.iter().map(|value| (value.id, value)).collect();
+
+    let mut diff = TaxonomyDiff::default();
+
+    for (id, old_value) in old.iter() {
+        let Some(new_value) = new.get(id) else {
+            diff.removed.push((*old_value).clone());
+            continue;
+        };
+
+        if old_value.name != new_value.name || old_value.tag != new_value.tag {
+            diff.renamed.push(RenamedTerm {
+                id: *id,
+                old_name: old_value.name.clone(),
+                new_name: new_value.name.clone(),
+                old_tag: old_value.tag.clone(),
+                new_tag: new_value.tag.clone(),
+            });
+        }
+    }
+
+    diff.added = new
+        .iter()
+        .filter(|(id, _)| !old.contains_key(id))
+        .map(|(_, value)| (*value).clone())
+        .collect();
+
+    diff
+}
+
+/// Compares all taxonomies of two snapshots
+pub fn diff_filters(old: &FiltersExportV1, new: &FiltersExportV1) -> FiltersDiff {
+    let taxonomies = Taxonomy::ALL
+        .into_iter()
+        .map(|taxonomy| {
+            let old = old.filters.get(&taxonomy).map_or(&[][..], Vec::as_slice);
+            let new = new.filters.get(&taxonomy).map_or(&[][..], Vec::as_slice);
+
+            (taxonomy, diff_taxonomy(old, new))
+        })
+        .collect();
+
+    FiltersDiff {
+        old_download_date: old.download_date.clone(),
+        new_download_date: new.download_date.clone(),
+        taxonomies,
+    }
+}
+
+fn read_snapshot(path: &PathBuf) -> anyhow::Result<FiltersExportV1> {
+    let text = read_to_string(path)
+        .with_context(|| format!("Reading {} failed", path.to_string_lossy()))?;
+
+    serde_json::from_str(&text)
+        .with_context(|| format!("Parsing {} failed", path.to_string_lossy()))
+}
+
+fn print_diff(diff: &FiltersDiff) {
+    println!(
+        "Changes from {} to {}",
+        diff.old_download_date, diff.new_download_date
+    );
+
+    for (taxonomy, diff) in diff.taxonomies.iter() {
+        if diff.is_empty() {
+            println!("\n{taxonomy}: no changes");
+            continue;
+        }
+
+        println!(
+            "\n{taxonomy}: {added} added, {removed} removed, {renamed} renamed",
+            added = diff.added.len(),
+            removed = diff.removed.len(),
+            renamed = diff.renamed.len(),
+        );
+
+        for value in diff.added.iter() {
+            println!("  + {: >6} \"{}\" ({})", value.id, value.name, value.tag);
+        }
+        for value in diff.removed.iter() {
+            println!("  - {: >6} \"{}\" ({})", value.id, value.name, value.tag);
+        }
+        for term in diff.renamed.iter() {
+            println!(
+                "  ~ {: >6} \"{}\" ({}) -> \"{}\" ({})",
+                term.id, term.old_name, term.old_tag, term.new_name, term.new_tag
+            );
+        }
+    }
+}
+
+/// Prints the changes between two snapshot files and optionally writes them as JSON to `output`
+pub fn diff_filter_files(
+    old: PathBuf,
+    new: PathBuf,
+    output: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let diff = diff_filters(&read_snapshot(&old)?, &read_snapshot(&new)?);
+
+    print_diff(&diff);
+
+    if let Some(output) = output {
+        std::fs::write(&output, serde_json::to_string_pretty(&diff)?)
+            .context("Failed to create the JSON file.\nTry to specify another path.\n")?;
+        println!(
+            "\nCreated JSON file with the changes at \"{}\"",
+            output.to_string_lossy()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(id: u64, name: &str, tag: &str) -> FilterValue {
+        FilterValue {
+            name: name.to_string(),
+            id,
+            tag: tag.to_string(),
+        }
+    }
+
+    #[test]
+    fn matches_terms_by_id() {
+        let old = [
+            value(1, "Kept", "kept"),
+            value(2, "Removed", "removed"),
+            value(3, "Old name", "old-name"),
+            value(4, "Retagged", "old-tag"),
+        ];
+        let new = [
+            value(5, "Added", "added"),
+            value(4, "Retagged", "new-tag"),
+            value(3, "New name", "old-name"),
+            value(1, "Kept", "kept"),
+        ];
+
+        let diff = diff_taxonomy(&old, &new);
+
+        assert_eq!(diff.added, [value(5, "Added", "added")]);
+        assert_eq!(diff.removed, [value(2, "Removed", "removed")]);
+
+        let renamed: Vec<_> = diff
+            .renamed
+            .iter()
+            .map(|term| (term.id, term.new_name.as_str(), term.new_tag.as_str()))
+            .collect();
+        assert_eq!(
+            renamed,
+            [(3, "New name", "old-name"), (4, "Retagged", "new-tag")]
+        );
+    }
+
+    #[test]
+    fn equal_snapshots_have_no_changes() {
+        let values = [value(1, "A", "a"), value(2, "B", "b")];
+
+        assert!(diff_taxonomy(&values, &values).is_empty());
+        assert!(diff_taxonomy(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn diffs_missing_taxonomies_as_empty() {
+        let old = FiltersExportV1 {
+            hdpc_dl_version: 1,
+            program_version: "2.10.0".to_string(),
+            download_date: "2024-01-01T00:00:00Z".to_string(),
+            filters: BTreeMap::new(),
+        };
+        let new = FiltersExportV1 {
+            hdpc_dl_version: 1,
+            program_version: "2.10.0".to_string(),
+            download_date: "2024-02-01T00:00:00Z".to_string(),
+            filters: BTreeMap::from([(Taxonomy::Tags, vec![value(1, "A", "a")])]),
+        };
+
+        let diff = diff_filters(&old, &new);
+
+        assert_eq!(diff.taxonomies.len(), Taxonomy::ALL.len());
+        assert_eq!(diff.taxonomies[&Taxonomy::Tags].added.len(), 1);
+        assert!(diff.taxonomies[&Taxonomy::Artist].is_empty());
+    }
+}
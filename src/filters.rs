@@ -1,77 +1,235 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use chrono::Utc;
+use html_escape::decode_html_entities;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{constants, db::Db, fetcher::Fetcher, import::import_filter_values};
+
+/// A snapshot of all filter lists, as written to disk by [`get_filters`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FiltersExportV1 {
+    /// The version of the JSON document
+    pub hdpc_dl_version: i32,
+
+    /// The version of this software used for downloading
+    pub program_version: String,
+
+    /// An RFC 3339 timestamp of the time this was downloaded
+    pub download_date: String,
+
+    /// The terms of every taxonomy, sorted by id
+    pub filters: BTreeMap<Taxonomy, Vec<FilterValue>>,
+}
+
+/// Downloads all filter lists, writes them to a snapshot file in `dest`, and stores them in the database
+pub async fn get_filters(db: &Db, fetcher: &dyn Fetcher, dest: &str) -> Result<(), anyhow::Error> {
+    let texts = get_filters_text(fetcher).await?;
+
+    let mut filters = BTreeMap::new();
+    for (taxonomy, text) in Taxonomy::ALL.into_iter().zip(texts) {
+        let mut values = decode_filters(&text)
+            .with_context(|| format!("Decoding the {taxonomy} filters failed"))?;
+        values.sort_by_key(|value| value.id);
 
-use crate::constants;
+        println!("Got {count: >5} {taxonomy} filters", count = values.len());
+        filters.insert(taxonomy, values);
+    }
 
-// TODO remove the following allow
-#[allow(unreachable_code)]
-pub async fn get_filters() -> Result<(), anyhow::Error> {
-    todo!("The download of filters from the search engine");
+    let download_date = Utc::now().to_rfc3339();
+    let export = FiltersExportV1 {
+        hdpc_dl_version: constants::FILTERS_VERSION,
+        program_version: constants::VERSION.to_string(),
+        download_date: download_date.clone(),
+        filters,
+    };
 
-    let client = reqwest::Client::new();
+    // Create the destination folder if it doesn't exist
+    std::fs::create_dir_all(dest)
+        .context("Failed to create directory.\nTry to specify another path.\n")?;
 
-    // let (artist, category, characters, groups, tags, parody) = get_filters_text(client).await?;
-    let mut res = get_filters_text(client).await?;
+    let json_path = dest.to_owned() + "/" + &download_date + constants::FILTERS_SUFFIX;
+    std::fs::write(&json_path, serde_json::to_string_pretty(&export)?)
+        .context("Failed to create the JSON file.\nTry to specify another path.\n")?;
+    println!("Created JSON file with filters at \"{json_path}\"");
 
-    // Convert the whatever's into valid JSON documents
-    for text in res.iter_mut() {
-        text.pop();
-        text.remove(0);
-        *text = text.replace("\\", "");
+    for (taxonomy, values) in export.filters {
+        let report = import_filter_values(db, taxonomy, values.into_iter()).await?;
+        report.print(taxonomy, constants::API_FILTER_PATH);
     }
 
     Ok(())
 }
 
-async fn get_filters_text(client: reqwest::Client) -> Result<[String; 6], anyhow::Error> {
-    let artist = client
-        .post(constants::API_FILTER_PATH)
-        .body("tax=artist")
-        .send()
-        .await?
-        .text()
-        .await?;
-    let category = client
-        .post(constants::API_FILTER_PATH)
-        .body("tax=category")
-        .send()
-        .await?
-        .text()
-        .await?;
-    let characters = client
-        .post(constants::API_FILTER_PATH)
-        .body("tax=characters")
-        .send()
-        .await?
-        .text()
-        .await?;
-    let groups = client
-        .post(constants::API_FILTER_PATH)
-        .body("tax=groups")
-        .send()
-        .await?
-        .text()
-        .await?;
-    let tags = client
-        .post(constants::API_FILTER_PATH)
-        .body("tax=tags")
-        .send()
-        .await?
-        .text()
-        .await?;
-    let parody = client
-        .post(constants::API_FILTER_PATH)
-        .body("tax=parody")
-        .send()
-        .await?
-        .text()
-        .await?;
+/// Decodes a response of the filter API into its terms
+///
+/// The API returns a JSON document wrapped in a JSON string, holding either an object or an array.
+fn decode_filters(text: &str) -> anyhow::Result<Vec<FilterValue>> {
+    let mut value: Value = serde_json::from_str(text)?;
+
+    while let Value::String(inner) = value {
+        value = serde_json::from_str(&inner)?;
+    }
+
+    let values = match value {
+        Value::Object(map) => map.into_iter().map(|(_, value)| value).collect(),
+        Value::Array(list) => list,
+        other => anyhow::bail!("Expected an object or an array, got {other}"),
+    };
+
+    values
+        .into_iter()
+        .map(|value| Ok(serde_json::from_value::<FilterValue>(value)?.normalize()))
+        .collect()
+}
+
+/// Requests all filter lists concurrently
+async fn get_filters_text(fetcher: &dyn Fetcher) -> Result<[String; 6], anyhow::Error> {
+    let get = |taxonomy: Taxonomy| async move {
+        info!("Downloading the {taxonomy} filters");
+
+        fetcher
+            .post_form(constants::API_FILTER_PATH, &[("tax", taxonomy.as_str())])
+            .await
+    };
+
+    let (artist, category, characters, groups, tags, parody) = tokio::try_join!(
+        get(Taxonomy::Artist),
+        get(Taxonomy::Category),
+        get(Taxonomy::Characters),
+        get(Taxonomy::Groups),
+        get(Taxonomy::Tags),
+        get(Taxonomy::Parody),
+    )?;
+
     Ok([artist, category, characters, groups, tags, parody])
 }
 
-pub type Filter = (u16, FilterValue);
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FilterValue {
-    name: String,
-    id: u64,
-    tag: String,
+    pub name: String,
+    pub id: u64,
+    pub tag: String,
+}
+
+impl FilterValue {
+    /// Decodes HTML entities and trims whitespace, as older versions stored the raw API values
+    pub fn normalize(self) -> Self {
+        Self {
+            name: decode_html_entities(self.name.trim()).trim().to_string(),
+            id: self.id,
+            tag: self.tag.trim().to_lowercase(),
+        }
+    }
+}
+
+/// The global filter lists of the site
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Taxonomy {
+    Artist,
+    Category,
+    Characters,
+    Groups,
+    Tags,
+    Parody,
+}
+
+impl Taxonomy {
+    /// All taxonomies in the order of the filter API
+    pub const ALL: [Taxonomy; 6] = [
+        Taxonomy::Artist,
+        Taxonomy::Category,
+        Taxonomy::Characters,
+        Taxonomy::Groups,
+        Taxonomy::Tags,
+        Taxonomy::Parody,
+    ];
+
+    /// The name used by the filter API, which is also used as the database table name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Taxonomy::Artist => "artist",
+            Taxonomy::Category => "category",
+            Taxonomy::Characters => "characters",
+            Taxonomy::Groups => "groups",
+            Taxonomy::Tags => "tags",
+            Taxonomy::Parody => "parody",
+        }
+    }
+}
+
+impl std::str::FromStr for Taxonomy {
+    type Err = anyhow::Error;
+
+    /// Accepts the API names as well as their singular forms, e.g. `tag` for [`Taxonomy::Tags`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "artist" | "artists" => Ok(Taxonomy::Artist),
+            "category" | "categories" => Ok(Taxonomy::Category),
+            "character" | "characters" => Ok(Taxonomy::Characters),
+            "group" | "groups" => Ok(Taxonomy::Groups),
+            "tag" | "tags" => Ok(Taxonomy::Tags),
+            "parody" | "parodies" => Ok(Taxonomy::Parody),
+            other => anyhow::bail!("Unknown taxonomy \"{other}\""),
+        }
+    }
+}
+
+impl std::fmt::Display for Taxonomy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn decodes_plain_arrays() {
+        let text = json!([
+            {"name": "Big Breasts", "id": 12, "tag": "big-breasts"},
+            {"name": " Tom &amp; Jerry ", "id": 34, "tag": " Tom-Jerry "},
+        ])
+        .to_string();
+
+        assert_eq!(
+            decode_filters(&text).unwrap(),
+            [
+                FilterValue {
+                    name: "Big Breasts".to_string(),
+                    id: 12,
+                    tag: "big-breasts".to_string(),
+                },
+                FilterValue {
+                    name: "Tom & Jerry".to_string(),
+                    id: 34,
+                    tag: "tom-jerry".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_string_wrapped_json() {
+        let inner = json!({"12": {"name": "Milf", "id": 12, "tag": "milf"}}).to_string();
+        let text = serde_json::to_string(&inner).unwrap();
+
+        assert_eq!(
+            decode_filters(&text).unwrap(),
+            [FilterValue {
+                name: "Milf".to_string(),
+                id: 12,
+                tag: "milf".to_string(),
+            }]
+        );
+
+        assert!(decode_filters(&serde_json::to_string("12").unwrap()).is_err());
+    }
 }
@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, fs::read_to_string, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::{
+    db::{self, Db, TaxonomyTerm},
+    filters::{FilterValue, Taxonomy},
+};
+
+/// A term whose stored values differ from the imported ones
+#[derive(Debug)]
+struct Conflict {
+    id: u64,
+    stored: TaxonomyTerm,
+    imported: TaxonomyTerm,
+}
+
+/// The outcome of importing one taxonomy
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    inserted: usize,
+    unchanged: usize,
+    conflicts: Vec<Conflict>,
+
+    /// Terms created from the links of posts before the filter lists were fetched, which were merged into these
+    merged: usize,
+}
+
+impl ImportReport {
+    /// Prints the counts and every conflict; `source` describes where the terms came from
+    pub fn print(&self, taxonomy: Taxonomy, source: &str) {
+        println!(
+            "Imported {taxonomy} from \"{source}\": {inserted} new, {unchanged} unchanged, {conflicts} conflicting",
+            inserted = self.inserted,
+            unchanged = self.unchanged,
+            conflicts = self.conflicts.len(),
+        );
+        if self.merged > 0 {
+            println!(
+                "  Merged {} {taxonomy} terms found on posts into the imported ones",
+                self.merged
+            );
+        }
+
+        for conflict in self.conflicts.iter() {
+            println!(
+                "  {taxonomy} {id}: \"{old_name}\" ({old_tag}) replaced by \"{new_name}\" ({new_tag})",
+                id = conflict.id,
+                old_name = conflict.stored.name,
+                old_tag = conflict.stored.tag,
+                new_name = conflict.imported.name,
+                new_tag = conflict.imported.tag,
+            );
+        }
+    }
+}
+
+/// Imports the taxonomy files written by older versions into the database
+///
+/// Existing terms are overwritten by the imported ones; every differing term is reported.
+pub async fn import_filter_files(
+    db: &Db,
+    files: Vec<(Taxonomy, Option<PathBuf>)>,
+) -> anyhow::Result<()> {
+    for (taxonomy, path) in files {
+        let Some(path) = path else {
+            continue;
+        };
+
+        let text =
+            read_to_string(&path).with_context(|| format!("Reading the {taxonomy} file failed"))?;
+        let values: BTreeMap<u16, FilterValue> = serde_json::from_str(&text)
+            .with_context(|| format!("Parsing the {taxonomy} file failed"))?;
+
+        let report = import_filter_values(db, taxonomy, values.into_values()).await?;
+        report.print(taxonomy, &path.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// Normalizes and upserts terms, comparing them to the stored ones
+pub async fn import_filter_values(
+    db: &Db,
+    taxonomy: Taxonomy,
+    values: impl Iterator<Item = FilterValue>,
+) -> anyhow::Result<ImportReport> {
+    let mut report = ImportReport::default();
+
+    let values: Vec<_> = values.map(FilterValue::normalize).collect();
+
+    for value in values.iter() {
+        let imported = TaxonomyTerm::from(value);
+
+        match db::upsert_taxonomy_term(db, taxonomy, value).await? {
+            None => report.inserted += 1,
+            Some(stored) if stored == imported => report.unchanged += 1,
+            Some(stored) => report.conflicts.push(Conflict {
+                id: value.id,
+                stored,
+                imported,
+            }),
+        }
+    }
+
+    report.merged = db::merge_placeholder_terms(db, taxonomy, &values).await?;
+
+    Ok(report)
+}
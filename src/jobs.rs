@@ -1,11 +1,20 @@
-use std::{collections::HashMap, fs::read_to_string, path::PathBuf};
+use std::{fmt, fs::read_to_string, path::Path, path::PathBuf, sync::Arc};
 
 use anyhow::Context;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::filters::{Filter, FilterValue};
+use crate::{
+    db::Db,
+    downloader::{crawl_download, download_from_url},
+    fetcher::Fetcher,
+    filters::{get_filters, Taxonomy},
+    import::import_filter_files,
+    library::import_library,
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 enum Job {
     /// Gets all global filter lists (artist, category, characters, groups, tags, and parody)
     FetchFilters,
@@ -13,7 +22,7 @@ enum Job {
     /// Downloads one comic from a URL
     DownloadOne { url: String, destination: PathBuf },
 
-    /// Crawls a URL for comics without downloading them
+    /// Crawls a URL for comics without downloading them, exporting the crawl result to the destination of the run
     CrawlUrlIndex { url: String },
 
     /// Implies
@@ -31,20 +40,250 @@ enum Job {
         tags_path: Option<PathBuf>,
         parody_path: Option<PathBuf>,
     },
+
+    /// Indexes all downloaded posts below `root`, regardless of their export version
+    ImportLibrary { root: PathBuf },
+}
+
+/// The root of a TOML jobs file, because TOML documents can't be a bare array
+///
+/// ```toml
+/// [[jobs]]
+/// FetchFilters = {}
+///
+/// [[jobs]]
+/// DownloadOne = { url = "https://...", destination = "./out" }
+/// ```
+#[derive(Debug, Deserialize)]
+struct JobsTable {
+    jobs: Vec<Job>,
+}
+
+/// The formats a jobs file can be written in, determined by its file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobsFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl JobsFormat {
+    fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("json") => Ok(Self::Json),
+            Some("toml") => Ok(Self::Toml),
+            Some("yaml" | "yml") => Ok(Self::Yaml),
+            _ => anyhow::bail!(
+                "Unknown jobs file format of {}; expected a .json, .toml, .yaml, or .yml file",
+                path.to_string_lossy()
+            ),
+        }
+    }
 }
 
-pub async fn run_jobs_from_path(path: PathBuf, verbosity: u8) -> anyhow::Result<()> {
+/// Describes where and why a jobs file couldn't be parsed
+#[derive(Debug)]
+pub struct JobsParseError {
+    /// The 1-based line and column of the problem, if the parser reports one
+    pub location: Option<(usize, usize)>,
+
+    /// The path to the offending field, e.g. `[2].DownloadOne.url`
+    pub field: String,
+
+    /// The description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for JobsParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((line, column)) = self.location {
+            write!(f, "line {line}, column {column}: ")?;
+        }
+
+        // An empty path (".") means the problem is at the root of the document
+        if self.field != "." {
+            write!(f, "at `{}`: ", self.field)?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for JobsParseError {}
+
+/// Removes the ` at line X column Y` suffix serde_json and serde_yaml add to their messages
+fn strip_location(message: String) -> String {
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+/// Converts a byte offset into a 1-based line and column
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1) + 1;
+
+    (line, column)
+}
+
+fn parse_jobs(text: &str, format: JobsFormat) -> Result<Vec<Job>, JobsParseError> {
+    match format {
+        JobsFormat::Json => {
+            let deserializer = &mut serde_json::Deserializer::from_str(text);
+
+            serde_path_to_error::deserialize(deserializer).map_err(|e| JobsParseError {
+                field: e.path().to_string(),
+                location: Some((e.inner().line(), e.inner().column())),
+                message: strip_location(e.inner().to_string()),
+            })
+        }
+
+        JobsFormat::Toml => {
+            let deserializer = toml::Deserializer::new(text);
+
+            serde_path_to_error::deserialize(deserializer)
+                .map(|table: JobsTable| table.jobs)
+                .map_err(|e| JobsParseError {
+                    field: e.path().to_string(),
+                    location: e.inner().span().map(|span| line_column(text, span.start)),
+                    message: e.inner().message().to_string(),
+                })
+        }
+
+        JobsFormat::Yaml => {
+            let deserializer = serde_yaml::Deserializer::from_str(text);
+
+            serde_path_to_error::deserialize(deserializer).map_err(|e| {
+                let message = strip_location(e.inner().to_string());
+
+                // serde_yaml prefixes its own path, which is redundant
+                let message = match message.split_once(": ") {
+                    Some((path, rest)) if path.starts_with('.') => rest.to_string(),
+                    _ => message,
+                };
+
+                JobsParseError {
+                    field: e.path().to_string(),
+                    location: e.inner().location().map(|l| (l.line(), l.column())),
+                    message,
+                }
+            })
+        }
+    }
+}
+
+/// Reads and parses a jobs file in any of the supported formats
+fn read_jobs(path: &Path) -> anyhow::Result<Vec<Job>> {
+    let format = JobsFormat::from_path(path)?;
+    let text = read_to_string(path).context("Reading the jobs file failed")?;
+
+    parse_jobs(&text, format)
+        .with_context(|| format!("Parsing the jobs file {} failed", path.to_string_lossy()))
+}
+
+/// Checks a jobs file without running any of its jobs
+pub fn validate_jobs_file(path: PathBuf) -> anyhow::Result<()> {
+    let jobs = read_jobs(&path)?;
+
+    println!(
+        "{} is valid and contains {} job(s)",
+        path.to_string_lossy(),
+        jobs.len()
+    );
+
+    Ok(())
+}
+
+/// Writes the JSON Schema of a jobs file to `path` or to stdout
+///
+/// The schema describes the JSON & YAML layout; TOML files wrap the same array in a `jobs` key.
+pub fn write_jobs_schema(path: Option<PathBuf>) -> anyhow::Result<()> {
+    let schema = schemars::schema_for!(Vec<Job>);
+    let serialized = serde_json::to_string_pretty(&schema)?;
+
+    match path {
+        Some(path) => {
+            std::fs::write(&path, serialized).context("Failed to write the schema file")?;
+            println!("Created JSON schema at \"{}\"", path.to_string_lossy());
+        }
+        None => println!("{serialized}"),
+    }
+
+    Ok(())
+}
+
+/// Crawls the first result page of `url` like `crawl` with its default options
+async fn crawl(
+    db: &Db,
+    fetcher: &Arc<dyn Fetcher>,
+    url: &str,
+    dest: &str,
+    verbosity: u8,
+    no_download: bool,
+) -> anyhow::Result<()> {
+    crawl_download(
+        db,
+        fetcher,
+        url,
+        dest,
+        verbosity.into(),
+        false,
+        0,
+        0,
+        false,
+        0,
+        no_download,
+        None,
+        &[],
+    )
+    .await
+}
+
+pub async fn run_jobs_from_path(
+    db: &Db,
+    fetcher: &Arc<dyn Fetcher>,
+    path: PathBuf,
+    dest: &str,
+    verbosity: u8,
+) -> anyhow::Result<()> {
     log::info!("Performing jobs from {}", path.to_string_lossy());
 
-    let jobs = read_to_string(&path).context("Reading the jobs file failed")?;
-    let jobs: Vec<Job> = serde_json::from_str(&jobs).context("Parsing the jobs file failed")?;
+    let jobs = read_jobs(&path)?;
 
     for job in jobs {
         match job {
-            Job::FetchFilters => todo!(),
-            Job::DownloadOne { url, destination } => todo!(),
-            Job::CrawlUrlIndex { url } => todo!(),
-            Job::CrawlUrlDownload { url, destination } => todo!(),
+            Job::FetchFilters => get_filters(db, fetcher.as_ref(), dest).await?,
+            Job::DownloadOne { url, destination } => {
+                download_from_url(
+                    db,
+                    fetcher,
+                    url,
+                    destination.to_string_lossy().to_string(),
+                    verbosity.into(),
+                    false,
+                    false,
+                    None,
+                )
+                .await?
+            }
+            Job::CrawlUrlIndex { url } => crawl(db, fetcher, &url, dest, verbosity, true).await?,
+            Job::CrawlUrlDownload { url, destination } => {
+                crawl(
+                    db,
+                    fetcher,
+                    &url,
+                    &destination.to_string_lossy(),
+                    verbosity,
+                    false,
+                )
+                .await?
+            }
             Job::ImportFromJson {
                 artist_path,
                 category_path,
@@ -53,15 +292,71 @@ pub async fn run_jobs_from_path(path: PathBuf, verbosity: u8) -> anyhow::Result<
                 tags_path,
                 parody_path,
             } => {
-                if let Some(artists) = artist_path {
-                    let artists =
-                        read_to_string(&artists).context("Reading the artists file failed")?;
-                    let artists: HashMap<u16, FilterValue> =
-                        serde_json::from_str(&artists).context("Parsing the jobs file failed")?;
-                };
+                import_filter_files(
+                    db,
+                    vec![
+                        (Taxonomy::Artist, artist_path),
+                        (Taxonomy::Category, category_path),
+                        (Taxonomy::Characters, characters_path),
+                        (Taxonomy::Groups, groups_path),
+                        (Taxonomy::Tags, tags_path),
+                        (Taxonomy::Parody, parody_path),
+                    ],
+                )
+                .await?
             }
+            Job::ImportLibrary { root } => import_library(db, root).await?,
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_column_is_one_based() {
+        let text = "[[jobs]]\nFetchFilters = {}\n";
+
+        assert_eq!(line_column(text, 0), (1, 1));
+        assert_eq!(line_column(text, 8), (1, 9));
+        assert_eq!(line_column(text, 9), (2, 1));
+        assert_eq!(line_column(text, 14), (2, 6));
+    }
+
+    #[test]
+    fn line_column_clamps_offsets_past_the_end() {
+        assert_eq!(line_column("ab\ncd", 100), (2, 3));
+    }
+
+    #[test]
+    fn reports_where_a_toml_job_is_invalid() {
+        let text = "[[jobs]]\nFetchFilters = {}\n\n[[jobs]]\nCrawlUrlIndex = { uri = \"x\" }\n";
+        let e = parse_jobs(text, JobsFormat::Toml).unwrap_err();
+
+        assert_eq!(e.location.map(|(line, _)| line), Some(5));
+        assert_eq!(e.field, "jobs[1].CrawlUrlIndex");
+    }
+
+    #[test]
+    fn parses_all_formats_alike() {
+        let toml = "[[jobs]]\nCrawlUrlIndex = { url = \"https://hdporncomics.com/\" }\n";
+        let json = r#"[{ "CrawlUrlIndex": { "url": "https://hdporncomics.com/" } }]"#;
+        let yaml = "- CrawlUrlIndex:\n    url: https://hdporncomics.com/\n";
+
+        for (text, format) in [
+            (toml, JobsFormat::Toml),
+            (json, JobsFormat::Json),
+            (yaml, JobsFormat::Yaml),
+        ] {
+            let jobs = parse_jobs(text, format).unwrap();
+
+            assert!(
+                matches!(&jobs[..], [Job::CrawlUrlIndex { url }] if url == "https://hdporncomics.com/"),
+                "{format:?}"
+            );
+        }
+    }
+}
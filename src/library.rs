@@ -0,0 +1,279 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tracing::warn;
+
+use crate::{
+    constants,
+    data::{
+        parse_count_text, ExportV4, ExportV5, ExportV6, ExportV7, ExportV8Buf, ExportVersion,
+        InfoboxRowBuf, LibraryPost, MetadataV5, TagLikeBuf,
+    },
+    db::{self, Db},
+};
+
+/// Recursively finds all files below `root` whose name is accepted by `matches`
+///
+/// Folders below `root` which can't be read are skipped with a warning.
+pub fn find_files(root: &Path, matches: impl Fn(&str) -> bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if dir != root => {
+                warn!("Skipped directory {}: {e}", dir.to_string_lossy());
+                continue;
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read directory {}", dir.to_string_lossy()))
+            }
+        };
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Skipped an entry of {}: {e}", dir.to_string_lossy());
+                    continue;
+                }
+            };
+            let path = entry.path();
+
+            if matches!(entry.file_type(), Ok(file_type) if file_type.is_dir()) {
+                dirs.push(path);
+            } else if matches(&entry.file_name().to_string_lossy()) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Recursively finds all exports below `root`
+pub fn find_exports(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    find_files(root, |name| name == constants::EXPORT_FILE_NAME)
+}
+
+/// Reads an export of any known version and upgrades it to a [`LibraryPost`]
+pub fn read_export(path: &Path) -> anyhow::Result<LibraryPost> {
+    let text = read_to_string(path).context("Reading the export failed")?;
+    let version: ExportVersion =
+        serde_json::from_str(&text).context("The export has no hdpc_dl_version")?;
+
+    let folder = path
+        .parent()
+        .map(|folder| folder.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let post = match version.hdpc_dl_version {
+        4 => upgrade_v4(serde_json::from_str(&text)?, folder),
+        5 => upgrade_v5(serde_json::from_str(&text)?, folder),
+        6 => upgrade_v6(serde_json::from_str(&text)?, folder),
+        7 => upgrade_v7(serde_json::from_str(&text)?, folder),
+        8 => upgrade_v8(serde_json::from_str(&text)?, folder),
+        version => anyhow::bail!("Unsupported export version {version}"),
+    };
+
+    Ok(post)
+}
+
+/// Scans `root` for exports of all versions and indexes them in the database
+pub async fn import_library(db: &Db, root: PathBuf) -> anyhow::Result<()> {
+    let exports = find_exports(&root)?;
+    println!(
+        "Found {} exports in \"{}\"",
+        exports.len(),
+        root.to_string_lossy()
+    );
+
+    let mut versions = BTreeMap::new();
+    let mut failures = vec![];
+
+    for path in exports {
+        let post = match read_export(&path) {
+            Ok(post) => post,
+            Err(e) => {
+                failures.push((path, e));
+                continue;
+            }
+        };
+
+        match db::store_post(db, &post).await {
+            Ok(_) => *versions.entry(post.hdpc_dl_version).or_insert(0) += 1,
+            Err(e) => failures.push((path, e.context("Storing the post failed"))),
+        }
+    }
+
+    for (version, count) in versions.iter() {
+        println!("Indexed {count: >5} exports of version {version}");
+    }
+
+    for (path, e) in failures.iter() {
+        println!("Skipped \"{}\": {e:#}", path.to_string_lossy());
+    }
+
+    println!(
+        "Indexed {indexed} posts, skipped {skipped}.",
+        indexed = versions.values().sum::<usize>(),
+        skipped = failures.len()
+    );
+
+    Ok(())
+}
+
+/// Parses a count which older versions stored as text, e.g. `"1,000"`
+fn parse_count(text: &str) -> Option<u32> {
+    parse_count_text(text)
+        .ok()
+        .flatten()
+        .and_then(|count| count.try_into().ok())
+}
+
+/// Version 4 & 5 metadata has no links, so the tags are converted without their `href`
+fn upgrade_metadata(metadata: Vec<MetadataV5>) -> Vec<InfoboxRowBuf> {
+    metadata
+        .into_iter()
+        .map(|row| InfoboxRowBuf::TagLike {
+            name: row.name,
+            tags: row
+                .entries
+                .into_iter()
+                .map(|text| TagLikeBuf {
+                    href: String::new(),
+                    text,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+fn upgrade_v4(export: ExportV4, folder: String) -> LibraryPost {
+    LibraryPost {
+        hdpc_dl_version: export.hdpc_dl_version,
+        program_version: None,
+        post_id: None,
+        title: export.title,
+        source_url: export.source_url,
+        download_date: export.download_date,
+        folder,
+        upvotes: parse_count(&export.upvotes),
+        downvotes: parse_count(&export.downvotes),
+        favorites: parse_count(&export.favorites),
+        views: None,
+        comment_count: export.comment_count,
+        metadata: upgrade_metadata(export.metadata),
+        chapters: vec![],
+        picture_urls: export.picture_urls,
+        comments: None,
+    }
+}
+
+fn upgrade_v5(export: ExportV5, folder: String) -> LibraryPost {
+    LibraryPost {
+        hdpc_dl_version: export.hdpc_dl_version,
+        program_version: Some(export.program_version),
+        post_id: Some(export.post_id),
+        title: export.title,
+        source_url: export.source_url,
+        download_date: export.download_date,
+        folder,
+        upvotes: Some(export.upvotes),
+        downvotes: Some(export.downvotes),
+        favorites: Some(export.favorites),
+        views: None,
+        comment_count: export.comment_count,
+        metadata: upgrade_metadata(export.metadata),
+        chapters: export.chapters,
+        picture_urls: export.picture_urls,
+        comments: None,
+    }
+}
+
+fn upgrade_v6(export: ExportV6, folder: String) -> LibraryPost {
+    LibraryPost {
+        hdpc_dl_version: export.hdpc_dl_version,
+        program_version: Some(export.program_version),
+        post_id: Some(export.post_id),
+        title: export.title,
+        source_url: export.source_url,
+        download_date: export.download_date,
+        folder,
+        upvotes: Some(export.upvotes),
+        downvotes: Some(export.downvotes),
+        favorites: Some(export.favorites),
+        views: None,
+        comment_count: export.comment_count,
+        metadata: export.metadata,
+        chapters: export.chapters,
+        picture_urls: export.picture_urls,
+        comments: None,
+    }
+}
+
+fn upgrade_v7(export: ExportV7, folder: String) -> LibraryPost {
+    LibraryPost {
+        hdpc_dl_version: export.hdpc_dl_version,
+        program_version: Some(export.program_version),
+        post_id: Some(export.post_id),
+        title: export.title,
+        source_url: export.source_url,
+        download_date: export.download_date,
+        folder,
+        upvotes: parse_count(&export.api_stats.post_likes),
+        downvotes: parse_count(&export.api_stats.post_dislikes),
+        favorites: parse_count(&export.api_stats.post_favorites),
+        views: parse_count(&export.api_stats.post_views),
+        comment_count: export.comment_count,
+        metadata: export.metadata,
+        chapters: export.chapters,
+        picture_urls: export.picture_urls,
+        comments: export.comments,
+    }
+}
+
+pub fn upgrade_v8(export: ExportV8Buf, folder: String) -> LibraryPost {
+    // Counts beyond u32 are treated as unknown rather than truncated
+    let count = |count: Option<u64>| count.and_then(|count| count.try_into().ok());
+
+    LibraryPost {
+        hdpc_dl_version: export.hdpc_dl_version,
+        program_version: Some(export.program_version),
+        post_id: Some(export.post_id),
+        title: export.title,
+        source_url: export.source_url,
+        download_date: export.download_date,
+        folder,
+        upvotes: count(export.api_stats.post_likes),
+        downvotes: count(export.api_stats.post_dislikes),
+        favorites: count(export.api_stats.post_favorites),
+        views: count(export.api_stats.post_views),
+        comment_count: export.comment_count,
+        metadata: export.metadata,
+        chapters: export.chapters,
+        picture_urls: export.picture_urls,
+        comments: export.comments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_counts_with_thousands_separators() {
+        assert_eq!(parse_count("1,000"), Some(1000));
+        assert_eq!(parse_count(" 42 "), Some(42));
+        assert_eq!(parse_count(""), None);
+        assert_eq!(parse_count("many"), None);
+        assert_eq!(parse_count("99,999,999,999"), None);
+    }
+}
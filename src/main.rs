@@ -1,16 +1,34 @@
 use old_cli::exec_cli;
 use tracing::Level;
 
+mod account;
 #[cfg(feature = "python_ffi")]
 mod bypass;
+mod command_fetcher;
+mod comment_media;
+mod comments;
+mod config;
 mod constants;
 mod data;
 mod db;
 mod downloader;
+mod fetcher;
+mod filter_diff;
 mod filters;
+mod http;
+mod import;
 mod jobs;
+mod library;
+mod migrate;
 mod old_cli;
 mod parser;
+mod query;
+mod schema;
+mod search;
+mod stats;
+#[cfg(test)]
+mod test_utils;
+mod text_search;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
@@ -19,14 +37,22 @@ async fn main() -> Result<(), anyhow::Error> {
         .with_max_level(Level::WARN)
         .finish();
 
-    let db_client = db::connect().await?;
-
     // use that subscriber to process traces emitted after this point
     tracing::subscriber::set_global_default(subscriber)?;
 
     let app = old_cli::make_cli_parser();
+    let matches = app.get_matches();
+
+    let db_config = old_cli::db_config(&matches)?;
+    let db_client = db::connect(&db_config).await?;
+    let writes_database = old_cli::writes_database(&matches);
+
+    let result = exec_cli(matches, &db_client).await;
 
-    exec_cli(app).await?;
+    // Save the database even if the command failed halfway through
+    if writes_database {
+        db::disconnect(&db_client, &db_config).await?;
+    }
 
-    Ok(())
+    result
 }
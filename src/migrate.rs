@@ -0,0 +1,374 @@
+use std::{
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{de::IgnoredAny, Deserialize};
+
+use crate::{
+    constants,
+    data::{ApiViewResponse, ChapterBuf, CrawlResultBuf, ExportV8Buf},
+    fetcher::Fetcher,
+    library::{find_files, read_export},
+    parser::{extract_chapters, extract_post_id, get_api_view},
+};
+
+/// Only needed to tell exports and crawl results apart before parsing them
+#[derive(Debug, Deserialize)]
+struct DocumentProbe {
+    hdpc_dl_version: i32,
+    posts: Option<IgnoredAny>,
+}
+
+/// Rewrites exports and crawl results to the latest versions in place, keeping a backup
+///
+/// Fields which older versions didn't store are only re-fetched if a fetcher is given.
+pub async fn migrate_paths(
+    fetcher: Option<&dyn Fetcher>,
+    paths: Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            files.append(&mut find_files(&path, |name| {
+                name == constants::EXPORT_FILE_NAME
+                    || name.ends_with(constants::CRAWL_RESULTS_SUFFIX)
+            })?);
+        } else {
+            files.push(path);
+        }
+    }
+
+    let (mut migrated, mut up_to_date, mut skipped) = (0, 0, 0);
+
+    for file in files.iter() {
+        match migrate_file(fetcher, file).await {
+            Ok(Some(version)) => {
+                println!(
+                    "Migrated \"{}\" from version {version}",
+                    file.to_string_lossy()
+                );
+                migrated += 1;
+            }
+            Ok(None) => up_to_date += 1,
+            Err(e) => {
+                println!("Skipped \"{}\": {e:#}", file.to_string_lossy());
+                skipped += 1;
+            }
+        }
+    }
+
+    println!("Migrated {migrated} files, {up_to_date} already up to date, {skipped} skipped.");
+
+    Ok(())
+}
+
+/// Migrates one file and returns the version it had, or `None` if it was up to date
+async fn migrate_file(fetcher: Option<&dyn Fetcher>, path: &Path) -> anyhow::Result<Option<i32>> {
+    let text = read_to_string(path).context("Reading the file failed")?;
+    let probe: DocumentProbe =
+        serde_json::from_str(&text).context("The file has no hdpc_dl_version")?;
+
+    let version = probe.hdpc_dl_version;
+    let latest = if probe.posts.is_some() {
+        constants::CRAWL_RESULT_VERSION
+    } else {
+        constants::EXPORT_VERSION
+    };
+
+    if version > latest {
+        anyhow::bail!("Version {version} is newer than this program understands ({latest})");
+    } else if version == latest {
+        return Ok(None);
+    }
+
+    let serialized = if probe.posts.is_some() {
+        let crawl_result = migrate_crawl_result(fetcher, &text).await?;
+        serde_json::to_string_pretty(&crawl_result)?
+    } else {
+        let export = migrate_export(fetcher, path).await?;
+        serde_json::to_string_pretty(&export)?
+    };
+
+    // E.g. "hdpc-info.json.v5.bak"
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{version}.bak"));
+
+    fs::copy(path, &backup).context("Failed to create the backup")?;
+    fs::write(path, serialized).context("Failed to write the migrated file")?;
+
+    Ok(Some(version))
+}
+
+async fn migrate_export(fetcher: Option<&dyn Fetcher>, path: &Path) -> anyhow::Result<ExportV8Buf> {
+    let post = read_export(path)?;
+
+    // Unknown counts are left empty instead of pretending to be 0
+    let count = |count: Option<u32>| count.map(u64::from);
+    let mut api_stats = ApiViewResponse {
+        post_dislikes: count(post.downvotes),
+        post_favorites: count(post.favorites),
+        post_id: post.post_id,
+        post_likes: count(post.upvotes),
+        post_views: count(post.views),
+    };
+    let mut post_id = post.post_id;
+    let mut chapters = post.chapters;
+
+    // Only missing fields are filled in, as the stored ones belong to the download date of the export
+    if let Some(fetcher) = fetcher {
+        // Versions before 5 didn't store the post id or the chapters
+        if post_id.is_none() || (post.hdpc_dl_version < 5 && chapters.is_empty()) {
+            let text = fetcher.get_page(&post.source_url).await?;
+
+            post_id = post_id.or_else(|| extract_post_id(&text));
+
+            if chapters.is_empty() {
+                chapters = extract_chapters(&text)
+                    .into_iter()
+                    .map(|chapter| ChapterBuf {
+                        name: chapter.name.into_owned(),
+                        url: chapter.url.to_string(),
+                    })
+                    .collect();
+            }
+        }
+
+        let counts = [
+            api_stats.post_dislikes,
+            api_stats.post_favorites,
+            api_stats.post_likes,
+            api_stats.post_views,
+        ];
+        if counts.iter().any(Option::is_none) {
+            let fetched = get_api_view(fetcher, &post.source_url).await?;
+
+            api_stats.post_dislikes = api_stats.post_dislikes.or(fetched.post_dislikes);
+            api_stats.post_favorites = api_stats.post_favorites.or(fetched.post_favorites);
+            api_stats.post_likes = api_stats.post_likes.or(fetched.post_likes);
+            api_stats.post_views = api_stats.post_views.or(fetched.post_views);
+        }
+    }
+
+    let post_id = post_id.context("The post id is unknown; allow re-fetching to fill it in")?;
+    api_stats.post_id = Some(post_id);
+
+    Ok(ExportV8Buf {
+        hdpc_dl_version: constants::EXPORT_VERSION,
+        program_version: post.program_version.unwrap_or_default(),
+        post_id,
+        title: post.title,
+        api_stats,
+        comment_count: post.comment_count,
+        download_date: post.download_date,
+        source_url: post.source_url,
+        metadata: post.metadata,
+        chapters,
+        picture_urls: post.picture_urls,
+        comments: post.comments,
+    })
+}
+
+async fn migrate_crawl_result(
+    fetcher: Option<&dyn Fetcher>,
+    text: &str,
+) -> anyhow::Result<CrawlResultBuf> {
+    let mut crawl_result: CrawlResultBuf =
+        serde_json::from_str(text).context("Parsing the crawl result failed")?;
+
+    crawl_result.hdpc_dl_version = constants::CRAWL_RESULT_VERSION;
+
+    if let Some(fetcher) = fetcher {
+        for post in crawl_result
+            .posts
+            .iter_mut()
+            .filter(|post| post.post_id == 0)
+        {
+            let text = fetcher.get_page(&post.url).await?;
+
+            post.post_id = extract_post_id(&text)
+                .and_then(|post_id| post_id.try_into().ok())
+                .with_context(|| format!("Couldn't extract the post id of {}", post.url))?;
+        }
+    }
+
+    Ok(crawl_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_utils::{temp_dir, StubFetcher};
+
+    const SOURCE_URL: &str = "https://hdporncomics.com/title/";
+    const VIEW_API: &str = "https://hdporncomics.com/?rest_route=%2Fapi%2Fv1%2Fview";
+
+    fn write(path: &Path, document: &Value) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, serde_json::to_string(document).unwrap()).unwrap();
+    }
+
+    fn read(path: &Path) -> Value {
+        serde_json::from_str(&read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn backup(path: &Path, version: i32) -> PathBuf {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".v{version}.bak"));
+        backup.into()
+    }
+
+    fn export_v5(version: i32) -> Value {
+        json!({
+            "hdpc_dl_version": version,
+            "program_version": "2.5.0",
+            "post_id": 1234,
+            "title": "Title",
+            "upvotes": 10,
+            "downvotes": 2,
+            "favorites": 0,
+            "comment_count": "3",
+            "download_date": "2023-05-01T00:00:00Z",
+            "source_url": SOURCE_URL,
+            "metadata": [],
+            "chapters": [],
+            "picture_urls": ["https://hdporncomics.com/1.jpg"],
+        })
+    }
+
+    fn export_v7(views: &str) -> Value {
+        json!({
+            "hdpc_dl_version": 7,
+            "program_version": "2.9.0",
+            "post_id": 1234,
+            "title": "Title",
+            "api_stats": {
+                "post_dislikes": "2",
+                "post_favorites": "5",
+                "post_id": 1234,
+                "post_likes": "10",
+                "post_views": views,
+            },
+            "comment_count": "3",
+            "download_date": "2023-05-01T00:00:00Z",
+            "source_url": SOURCE_URL,
+            "metadata": [],
+            "chapters": [],
+            "picture_urls": [],
+            "comments": null,
+        })
+    }
+
+    #[tokio::test]
+    async fn migrates_version_5_and_6_exports_without_a_fetcher() {
+        let dir = temp_dir("migrate-v5-v6");
+        let v5 = dir.join("v5").join(constants::EXPORT_FILE_NAME);
+        let v6 = dir.join("v6").join(constants::EXPORT_FILE_NAME);
+        write(&v5, &export_v5(5));
+        write(&v6, &export_v5(6));
+
+        migrate_paths(None, vec![dir.clone()]).await.unwrap();
+
+        for (path, version) in [(&v5, 5), (&v6, 6)] {
+            let migrated = read(path);
+            assert_eq!(migrated["hdpc_dl_version"], constants::EXPORT_VERSION);
+            assert_eq!(migrated["post_id"], 1234);
+            assert_eq!(migrated["download_date"], "2023-05-01T00:00:00Z");
+            assert_eq!(migrated["api_stats"]["post_likes"], 10);
+            assert_eq!(migrated["api_stats"]["post_favorites"], 0);
+            assert_eq!(migrated["api_stats"]["post_views"], Value::Null);
+
+            assert_eq!(read(&backup(path, version)), export_v5(version));
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_exports_without_a_post_id() {
+        let dir = temp_dir("migrate-v4");
+        let path = dir.join(constants::EXPORT_FILE_NAME);
+        let v4 = json!({
+            "hdpc_dl_version": 4,
+            "title": "Title",
+            "other_title": "",
+            "upvotes": "1,000",
+            "downvotes": "2",
+            "favorites": "0",
+            "comment_count": "3",
+            "download_date": "01.05.2020",
+            "source_url": SOURCE_URL,
+            "metadata": [{ "name": "Tags", "entries": ["Glasses"] }],
+            "picture_urls": [],
+        });
+        write(&path, &v4);
+
+        let e = migrate_file(None, &path).await.unwrap_err();
+
+        assert!(e.to_string().contains("post id is unknown"), "{e:#}");
+        assert_eq!(read(&path), v4);
+        assert!(!backup(&path, 4).exists());
+    }
+
+    #[tokio::test]
+    async fn migrates_crawl_results_without_a_fetcher() {
+        let dir = temp_dir("migrate-crawl");
+        let path = dir.join(format!("crawl{}", constants::CRAWL_RESULTS_SUFFIX));
+        let crawl_result = json!({
+            "hdpc_dl_version": 5,
+            "source_url": "https://hdporncomics.com/artist/some-artist/",
+            "download_date": "2023-05-01T00:00:00Z",
+            "posts": [{ "name": "Title", "url": SOURCE_URL }],
+        });
+        write(&path, &crawl_result);
+
+        assert_eq!(migrate_file(None, &path).await.unwrap(), Some(5));
+
+        let migrated = read(&path);
+        assert_eq!(migrated["hdpc_dl_version"], constants::CRAWL_RESULT_VERSION);
+        assert_eq!(migrated["posts"][0]["url"], SOURCE_URL);
+        assert_eq!(migrated["posts"][0]["post_id"], 0);
+        assert_eq!(read(&backup(&path, 5)), crawl_result);
+
+        // Migrated files are left alone
+        assert_eq!(migrate_file(None, &path).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn keeps_stored_counts_when_fetching() {
+        let dir = temp_dir("migrate-v7-complete");
+        let path = dir.join(constants::EXPORT_FILE_NAME);
+        write(&path, &export_v7("1,000"));
+        let fetcher = StubFetcher::default();
+
+        migrate_file(Some(&fetcher), &path).await.unwrap();
+
+        let stats = &read(&path)["api_stats"];
+        assert_eq!(stats["post_views"], 1000);
+        assert_eq!(stats["post_likes"], 10);
+        assert!(fetcher.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_fetches_missing_counts() {
+        let dir = temp_dir("migrate-v7-missing");
+        let path = dir.join(constants::EXPORT_FILE_NAME);
+        write(&path, &export_v7(""));
+        let mut fetcher = StubFetcher::default();
+        fetcher.api.insert(
+            format!("{VIEW_API} {SOURCE_URL}"),
+            r#"{"post_dislikes": 9, "post_favorites": 9, "post_id": 1234, "post_likes": 9, "post_views": 5000}"#
+                .to_string(),
+        );
+
+        migrate_file(Some(&fetcher), &path).await.unwrap();
+
+        let stats = &read(&path)["api_stats"];
+        assert_eq!(stats["post_views"], 5000);
+        assert_eq!(stats["post_likes"], 10);
+        assert_eq!(stats["post_dislikes"], 2);
+        assert_eq!(stats["post_favorites"], 5);
+        assert_eq!(fetcher.requests().len(), 1);
+    }
+}
@@ -1,7 +1,28 @@
-use crate::{constants, downloader, filters, jobs::run_jobs_from_path};
+use crate::{
+    account::{self, Login},
+    comments::{self, CommentFormat, CommentOptions},
+    config::{self, Config, Secret, CONFIG_FILE_NAME},
+    constants,
+    db::{self, Credentials, Db, DbConfig, DbLocation},
+    downloader,
+    fetcher::{create_fetcher, Fetcher, FetcherKind},
+    filter_diff,
+    filters::{self, FilterValue, Taxonomy},
+    http::{self, HttpSettings, Proxies},
+    jobs::{run_jobs_from_path, validate_jobs_file, write_jobs_schema},
+    library, migrate,
+    query::{self, LibraryQuery, OutputFormat, SortKey},
+    search::{self, SearchTerm},
+    stats::{self, Metric},
+    text_search,
+};
 use anyhow::Context;
-use clap::{Arg, ArgAction, Command};
-use tracing::info;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{info, warn};
 
 /// Parse and execute the command specified via the CLI
 pub fn make_cli_parser() -> clap::Command {
@@ -33,22 +54,114 @@ pub fn make_cli_parser() -> clap::Command {
         .default_value(val)
         .short('d')
         .long("destination"),
+      Arg::new("config")
+        .help("Sets the config file [default: hdpc-dl.toml in the destination, if it exists]")
+        .long("config")
+        .env("HDPC_DL_CONFIG"),
+      Arg::new("data dir")
+        .help("Sets the folder of the default database and the login session [default: hdpc-dl in the data folder of the user, e.g. ~/.local/share/hdpc-dl]")
+        .long("data-dir")
+        .env("HDPC_DL_DATA_DIR"),
+      Arg::new("database")
+        .help("Sets the database: mem://, mem://<file.surql>, rocksdb://<folder>, or a ws:// server [default: a database in the data folder]")
+        .long("database")
+        .env("HDPC_DL_DATABASE"),
+      Arg::new("db namespace")
+        .help("Sets the database namespace [default: hdpc-dl]")
+        .long("db-namespace")
+        .env("HDPC_DL_DB_NAMESPACE"),
+      Arg::new("db name")
+        .help("Sets the database name [default: hdpc-dl]")
+        .long("db-name")
+        .env("HDPC_DL_DB_NAME"),
+      Arg::new("db user")
+        .help("Signs in to a remote database as this user")
+        .long("db-user")
+        .env("HDPC_DL_DB_USER"),
+      Arg::new("db password")
+        .help("Sets the password of the database user (prefer the environment variable or the config file)")
+        .long("db-password")
+        .env("HDPC_DL_DB_PASSWORD")
+        .hide_env_values(true),
+      Arg::new("db level")
+        .help("Sets the level the database user is defined on [default: root]")
+        .long("db-level")
+        .env("HDPC_DL_DB_LEVEL")
+        .value_parser(["root", "namespace", "database"]),
       Arg::new("json only")
         .help("Only generate the JSON file")
         .short('j')
         .action(ArgAction::SetTrue)
         .long("json-only"),
-      #[cfg(feature = "python_ffi")]
+      Arg::new("fetcher")
+        .help("Sets how pages are requested: plain (reqwest), bypass (Python cloudscraper), command (--bypass-command), or offline (replays --recordings)")
+        .long("fetcher")
+        .env("HDPC_DL_FETCHER")
+        .value_parser(FetcherKind::NAMES)
+        .default_value("plain"),
       Arg::new("use bypass")
-        .help("Use a Python library to bypass scraping prevention measures")
+        .help("Use a Python library to bypass scraping prevention measures (same as --fetcher bypass)")
         .short('b')
         .action(ArgAction::SetTrue)
+        .conflicts_with("fetcher")
         .long("use-bypass"),
+      Arg::new("bypass command")
+        .help("The command which requests pages for --fetcher command, e.g. \"python3 scripts/cloudscraper_bypass.py\"")
+        .long("bypass-command")
+        .env("HDPC_DL_BYPASS_COMMAND"),
+      Arg::new("cookies")
+        .help("Sends the cookies of a Netscape cookies.txt or a JSON cookie export of a browser")
+        .long("cookies")
+        .env("HDPC_DL_COOKIES"),
+      Arg::new("user agent")
+        .help("Sets the User-Agent of all requests")
+        .long("user-agent")
+        .env("HDPC_DL_USER_AGENT"),
+      Arg::new("header")
+        .help("Sends an extra header with all requests, e.g. \"Accept-Language: en\" (repeatable)")
+        .short('H')
+        .long("header")
+        .action(ArgAction::Append),
+      Arg::new("proxy")
+        .help("Sends all requests through this HTTP(S) or SOCKS5 proxy, e.g. socks5h://127.0.0.1:9050; several are used in turns (repeatable)")
+        .long("proxy")
+        .env("HDPC_DL_PROXY")
+        .action(ArgAction::Append)
+        .value_delimiter(','),
+      Arg::new("host proxy")
+        .help("Sends the requests to a host and its subdomains through a proxy, or directly, e.g. \"example.com=direct\" (repeatable)")
+        .long("proxy-for")
+        .action(ArgAction::Append),
+      Arg::new("tor")
+        .help("Sends all requests through the Tor daemon on this computer (same as --proxy socks5h://127.0.0.1:9050)")
+        .long("tor")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("proxy"),
+      Arg::new("recordings")
+        .help("Saves every requested page and API response to this folder, or replays them with --fetcher offline")
+        .long("recordings")
+        .env("HDPC_DL_RECORDINGS"),
       Arg::new("get comments")
         .help("Download the comments of all targets (multiple requests)")
         .short('c')
         .action(ArgAction::SetTrue)
         .long("get-comments"),
+      Arg::new("comment format")
+        .help("The exports of the comments written next to every post, with --get-comments")
+        .long("comment-format")
+        .action(ArgAction::Append)
+        .value_delimiter(',')
+        .value_parser(CommentFormat::NAMES)
+        .default_values(["json", "html"]),
+      Arg::new("comment concurrency")
+        .help("The maximum number of comment pages requested at the same time")
+        .long("comment-concurrency")
+        .value_parser(clap::value_parser!(usize))
+        .default_value("8"),
+      Arg::new("archive comment media")
+        .help("Download the avatars of commenters and the media embedded in comments next to the comment exports")
+        .long("archive-comment-media")
+        .action(ArgAction::SetTrue),
       Arg::new("v")
         .short('v')
         .action(ArgAction::Count)
@@ -60,10 +173,33 @@ pub fn make_cli_parser() -> clap::Command {
     Command::new("run-jobs")
       .about("Run a job with db")
       .args(&[Arg::new("jobs-file-location")
-        .help("The path to the JSON file")
+        .help("The path to the JSON, TOML, or YAML file")
         .required(true)
       ])
     )
+    .subcommand(
+      Command::new("jobs")
+        .about("Inspect jobs files without running them")
+        .after_help(constants::LICENSE)
+        .subcommand_required(true)
+        .subcommand(
+          Command::new("validate")
+            .about("Checks a jobs file and reports the location of any problem")
+            .args(&[Arg::new("jobs-file-location")
+              .help("The path to the JSON, TOML, or YAML file")
+              .required(true)
+            ])
+        )
+        .subcommand(
+          Command::new("schema")
+            .about("Prints the JSON Schema of jobs files")
+            .args(&[Arg::new("output")
+              .help("Writes the schema to this path instead of stdout")
+              .short('o')
+              .long("output")
+            ])
+        )
+    )
     //
     // One
     .subcommand(
@@ -88,44 +224,544 @@ pub fn make_cli_parser() -> clap::Command {
             .help("Sets the URL of the page to be crawled")
             .required(true)
             .index(1),
+        ])
+        .args(crawl_args()),
+    )
+    //
+    // Search
+    .subcommand(
+      Command::new("search")
+        .about("Crawls the posts matching filters, which are resolved by name using the stored filter lists")
+        .after_help(constants::LICENSE)
+        .args(&[
+          Arg::new("QUERY")
+            .help("Sets the text to search for")
+            .index(1),
+          Arg::new("include")
+            .help("Only finds posts with this term, e.g. \"artist:Some Artist\" or \"tag:glasses\"")
+            .short('i')
+            .long("include")
+            .action(ArgAction::Append),
+          Arg::new("exclude")
+            .help("Skips the found posts with this term, which is checked on the crawled listing")
+            .short('x')
+            .long("exclude")
+            .action(ArgAction::Append),
+        ])
+        .args(crawl_args()),
+    )
+    //
+    // Account
+    .subcommand(
+      Command::new("account")
+        .about("Logs in to the site and crawls the lists of the account")
+        .after_help(constants::LICENSE)
+        .subcommand_required(true)
+        .subcommand(
+          Command::new("login")
+            .about("Logs in and saves the session to the data folder, where later runs load it from")
+            .args(&[
+              Arg::new("username")
+                .help("Sets the username [default: the one in the [account] section of the config file]")
+                .short('u')
+                .long("username")
+                .env("HDPC_DL_USERNAME"),
+              Arg::new("password")
+                .help("Sets the password (prefer the environment variable or the config file)")
+                .long("password")
+                .env("HDPC_DL_PASSWORD")
+                .hide_env_values(true),
+              Arg::new("session-cookie")
+                .help("Saves the wordpress_logged_in_... cookie of a logged in browser instead, as \"name=value\"")
+                .long("session-cookie")
+                .conflicts_with_all(["username", "password"]),
+            ])
+        )
+        .subcommand(Command::new("logout").about("Deletes the saved session"))
+        .subcommand(Command::new("status").about("Checks whether the site sees the requests as logged in"))
+        .subcommand(
+          Command::new("favorites")
+            .about("Crawls the favorites of the account (favorites_url in the config file) like the crawl command")
+            .args(crawl_args())
+        )
+        .subcommand(
+          Command::new("bookmarks")
+            .about("Crawls the bookmarks of the account (bookmarks_url in the config file) like the crawl command")
+            .args(crawl_args())
+        )
+    )
+    .subcommand(
+      Command::new("export-comments")
+        .about("Writes the comment exports (see --comment-format) of posts downloaded with comments")
+        .after_help(constants::LICENSE)
+        .args(&[Arg::new("PATH")
+          .help("The exports or folders to search for hdpc-info.json files")
+          .required(true)
+          .action(ArgAction::Append)
+          .index(1)]),
+    )
+    .subcommand(
+      Command::new("sync-comments")
+        .about("Fetches the new comments of stored posts and records added, edited, and deleted comments")
+        .after_help(constants::LICENSE)
+        .args(&[
+          Arg::new("POST")
+            .help("The ids or URLs of the posts (default: all posts with stored comments)")
+            .action(ArgAction::Append)
+            .index(1),
+          Arg::new("full")
+            .help("Fetches all comment pages, which is needed to notice deleted comments")
+            .long("full")
+            .action(ArgAction::SetTrue),
+        ]),
+    )
+    .subcommand(
+      Command::new("search-local")
+        .about("Searches the titles, infobox text, and comments of the stored posts without going online")
+        .after_help(constants::LICENSE)
+        .args(&[
+          Arg::new("QUERY")
+            .help("The words to search for; quote words to find them as a phrase, e.g. 'red \"big ship\"'")
+            .required(true)
+            .action(ArgAction::Append)
+            .index(1),
+          Arg::new("limit")
+            .help("Limits the results to n posts (0 for all)")
+            .short('l')
+            .long("limit")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("20"),
+        ]),
+    )
+    .subcommand(
+      Command::new("list")
+        .alias("query")
+        .about("Lists the downloaded posts in the database matching filters")
+        .after_help(constants::LICENSE)
+        .args(&[
+          Arg::new("include")
+            .help("Only lists posts with this term, e.g. \"artist:Some Artist\" or \"tag:glasses\"")
+            .short('i')
+            .long("include")
+            .action(ArgAction::Append),
+          Arg::new("exclude")
+            .help("Skips posts with this term, e.g. \"parody:Some Show\"")
+            .short('e')
+            .long("exclude")
+            .action(ArgAction::Append),
+          Arg::new("after")
+            .help("Only lists posts downloaded on or after this date (2024-01-31 or RFC 3339)")
+            .long("after"),
+          Arg::new("before")
+            .help("Only lists posts downloaded before this date (2024-01-31 or RFC 3339)")
+            .long("before"),
+          Arg::new("where")
+            .help("Only lists posts matching a condition on views, upvotes, downvotes, favorites, or pages, e.g. \"pages>=20\"")
+            .short('w')
+            .long("where")
+            .action(ArgAction::Append),
+          Arg::new("sort")
+            .help("Sorts the posts by this field")
+            .short('s')
+            .long("sort")
+            .value_parser(SortKey::NAMES)
+            .default_value("date"),
+          Arg::new("descending")
+            .help("Sorts in descending order")
+            .long("desc")
+            .action(ArgAction::SetTrue),
           Arg::new("limit")
+            .help("Limits the listing to n posts (0 for all)")
+            .short('l')
+            .long("limit")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("0"),
+          Arg::new("format")
+            .help("The output format")
+            .short('f')
+            .long("format")
+            .value_parser(OutputFormat::NAMES)
+            .default_value("table"),
+          Arg::new("output")
+            .help("Writes the JSON or CSV to this path instead of stdout")
+            .short('o')
+            .long("output"),
+        ]),
+    )
+    .subcommand(
+      Command::new("import-library")
+        .about("Indexes all downloaded posts in a folder, regardless of their export version")
+        .after_help(constants::LICENSE)
+        .args(&[Arg::new("root")
+          .help("The folder to search for hdpc-info.json files")
+          .required(true)
+          .index(1)]),
+    )
+    .subcommand(
+      Command::new("migrate")
+        .about("Rewrites exports and crawl results of older versions in place, keeping a backup")
+        .after_help(constants::LICENSE)
+        .args(&[
+          Arg::new("PATH")
+            .help("The files or folders to migrate")
+            .required(true)
+            .action(ArgAction::Append)
+            .index(1),
+          Arg::new("allow-fetch")
+            .help("Re-fetch fields older versions didn't store, like the post id and views")
+            .short('f')
+            .action(ArgAction::SetTrue)
+            .long("allow-fetch"),
+        ]),
+    )
+    .subcommand(
+      Command::new("get-filters")
+        .about("Downloads the filter api data and stores it in JSON files")
+        .after_help(constants::LICENSE),
+    )
+    .subcommand(
+      Command::new("diff-filters")
+        .about("Shows the terms added, removed, or renamed between two filter snapshots written by get-filters")
+        .after_help(constants::LICENSE)
+        .args(&[
+          Arg::new("OLD")
+            .help("The older snapshot")
+            .required(true)
+            .index(1),
+          Arg::new("NEW")
+            .help("The newer snapshot")
+            .required(true)
+            .index(2),
+          Arg::new("output")
+            .help("Also writes the changes as JSON to this path")
+            .short('o')
+            .long("output"),
+        ]),
+    )
+    //
+    // Post stats
+    .subcommand(
+      Command::new("stats")
+        .about("Charts and ranks the stats of stored posts over time")
+        .after_help(constants::LICENSE)
+        .subcommand_required(true)
+        .subcommand(
+          Command::new("history")
+            .about("Shows every observation of the stats of a post")
+            .args(&[
+              Arg::new("POST")
+                .help("The id or URL of the post")
+                .required(true),
+              metric_arg(),
+            ])
+        )
+        .subcommand(
+          Command::new("rank")
+            .about("Ranks the posts by how much their stats grew")
+            .args(&[
+              metric_arg(),
+              Arg::new("days")
+                .help("Only considers observations of the last n days")
+                .long("days")
+                .value_parser(clap::value_parser!(i64))
+                .default_value("30"),
+              Arg::new("limit")
+                .help("Limits the ranking to n posts (0 for all)")
+                .short('l')
+                .long("limit")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("20"),
+            ])
+        )
+    );
+
+    return app;
+}
+
+/// The stat to chart or rank by
+fn metric_arg() -> Arg {
+    Arg::new("by")
+        .help("The stat to chart or rank by")
+        .long("by")
+        .value_parser(Metric::NAMES)
+        .default_value("views")
+}
+
+/// The args shared by all commands using the crawl pipeline
+fn crawl_args() -> [Arg; 5] {
+    [
+        Arg::new("limit")
             .help("Limit to n finding(s) to be downloaded")
             .short('l')
             .long("limit")
+            .value_parser(clap::value_parser!(usize))
             .default_value("0"),
-          Arg::new("skip")
+        Arg::new("skip")
             .help("Skip the first n finding(s)")
             .short('s')
             .long("skip")
+            .value_parser(clap::value_parser!(usize))
             .default_value("0"),
-          Arg::new("retries")
+        Arg::new("retries")
             .help("How often to retry if a download fails")
             .short('r')
             .long("retries")
+            .value_parser(clap::value_parser!(usize))
             .default_value("0"),
-            Arg::new("paging")
+        Arg::new("paging")
             .help("Tries to continue on the next page withing the download limit & offset")
             .short('p')
+            .action(ArgAction::SetTrue)
             .long("paging"),
-            Arg::new("no-download")
+        Arg::new("no-download")
             .help("Exports the crawl result without downloading anything else")
             .short('n')
-            .long("no-download")
-        ]),
+            .action(ArgAction::SetTrue)
+            .long("no-download"),
+    ]
+}
+
+/// Runs the crawl pipeline on `url` with the options of [`crawl_args`], skipping posts with an `exclude`d term
+async fn exec_crawl(
+    db: &Db,
+    fetcher: &Arc<dyn Fetcher>,
+    url: &str,
+    matches: &ArgMatches,
+    sub_matches: &ArgMatches,
+    exclude: &[(Taxonomy, FilterValue)],
+) -> Result<(), anyhow::Error> {
+    let dest: &String = matches.get_one("destination").unwrap();
+    let comment_options = comment_options(matches)?;
+
+    downloader::crawl_download(
+        db,
+        fetcher,
+        url,
+        dest,
+        matches.get_count("v").into(),
+        matches.get_flag("json only"),
+        *sub_matches.get_one("limit").unwrap(),
+        *sub_matches.get_one("skip").unwrap(),
+        sub_matches.get_flag("paging"),
+        *sub_matches.get_one("retries").unwrap(),
+        sub_matches.get_flag("no-download"),
+        comment_options.as_ref(),
+        exclude,
     )
-    .subcommand(
-      Command::new("get-filters")
-        .about("Downloads the filter api data and stores it in JSON files")
-        .after_help(constants::LICENSE),
-    );
+    .await
+}
 
-    return app;
+/// Gets the comment options, or `None` if comments shouldn't be downloaded
+fn comment_options(matches: &ArgMatches) -> anyhow::Result<Option<CommentOptions>> {
+    if !matches.get_flag("get comments") {
+        return Ok(None);
+    }
+
+    comment_settings(matches).map(Some)
+}
+
+/// Gets the comment options regardless of --get-comments
+fn comment_settings(matches: &ArgMatches) -> anyhow::Result<CommentOptions> {
+    Ok(CommentOptions {
+        formats: matches
+            .get_many::<String>("comment format")
+            .unwrap_or_default()
+            .map(|format| format.parse())
+            .collect::<anyhow::Result<_>>()?,
+        concurrency: *matches.get_one("comment concurrency").unwrap(),
+        archive_media: matches.get_flag("archive comment media"),
+    })
+}
+
+/// Reads the config file given with --config, or the one in the destination if it exists
+fn load_config(matches: &ArgMatches) -> anyhow::Result<Config> {
+    let dest: &String = matches.get_one("destination").unwrap();
+
+    match matches.get_one::<String>("config") {
+        Some(path) => Config::load(Path::new(path), true),
+        None => Config::load(&Path::new(dest).join(CONFIG_FILE_NAME), false),
+    }
 }
 
-pub async fn exec_cli(app: Command) -> Result<(), anyhow::Error> {
-    // Parse the CLI arguments
-    let matches = app.get_matches();
+/// Gets the HTTP settings from the CLI arguments, environment variables, and the config file (in this order)
+///
+/// Headers and host proxies of the config file are used as well, unless one of the same name is given.
+fn http_settings(matches: &ArgMatches) -> anyhow::Result<HttpSettings> {
+    let file = load_config(matches)?.http;
 
+    let mut headers: Vec<_> = file.headers.into_iter().collect();
+    for header in matches.get_many::<String>("header").unwrap_or_default() {
+        headers.push(http::parse_header(header)?);
+    }
+
+    let rotation: Vec<String> = if matches.get_flag("tor") {
+        vec![constants::TOR_PROXY.to_string()]
+    } else {
+        match matches.get_many::<String>("proxy") {
+            Some(proxies) => proxies.cloned().collect(),
+            None => file.proxies,
+        }
+    };
+    let rotation = rotation
+        .iter()
+        .map(|proxy| http::parse_proxy(proxy))
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut host_proxies = vec![];
+    for (host, proxy) in file.host_proxies {
+        host_proxies.push(http::parse_host_proxy(&format!("{host}={proxy}"))?);
+    }
+    for rule in matches.get_many::<String>("host proxy").unwrap_or_default() {
+        let (host, proxy) = http::parse_host_proxy(rule)?;
+        host_proxies.retain(|(other, _)| *other != host);
+        host_proxies.push((host, proxy));
+    }
+
+    Ok(HttpSettings {
+        user_agent: matches
+            .get_one::<String>("user agent")
+            .cloned()
+            .or(file.user_agent),
+        headers,
+        cookies: matches
+            .get_one::<String>("cookies")
+            .map(Into::into)
+            .or(file.cookies)
+            .into_iter()
+            .chain(
+                data_dir(matches)
+                    .ok()
+                    .map(|dir| dir.join(constants::SESSION_FILE_NAME))
+                    .filter(|path| path.exists()),
+            )
+            .collect(),
+        proxies: Proxies::new(rotation, host_proxies),
+    })
+}
+
+/// The file the cookies of the logged in account are saved to
+fn session_path(matches: &ArgMatches) -> anyhow::Result<PathBuf> {
+    let dest: &String = matches.get_one("destination").unwrap();
+
+    // Older versions saved the session in the destination
+    let old = Path::new(dest).join(constants::SESSION_FILE_NAME);
+    if old.exists() {
+        warn!(
+            "Found a session of an older version at {}, which is no longer used; delete it",
+            old.to_string_lossy()
+        );
+    }
+
+    Ok(data_dir(matches)?.join(constants::SESSION_FILE_NAME))
+}
+
+/// The folder of the default database and the login session
+fn data_dir(matches: &ArgMatches) -> anyhow::Result<PathBuf> {
+    match matches.get_one::<String>("data dir") {
+        Some(dir) => Ok(dir.into()),
+        None => config::default_data_dir()
+            .context("This system has no data folder for the user; pass --data-dir or --database"),
+    }
+}
+
+/// Whether the command may change the database, so an in-memory database has to be saved afterwards
+pub fn writes_database(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        Some(("account", sub_matches)) => {
+            matches!(
+                sub_matches.subcommand_name(),
+                Some("favorites" | "bookmarks")
+            )
+        }
+        Some((name, _)) => matches!(
+            name,
+            "get"
+                | "crawl"
+                | "search"
+                | "run-jobs"
+                | "import-library"
+                | "get-filters"
+                | "sync-comments"
+        ),
+        None => false,
+    }
+}
+
+/// Gets the database settings from the CLI arguments, environment variables, and the config file (in this order)
+pub fn db_config(matches: &ArgMatches) -> anyhow::Result<DbConfig> {
+    let dest: &String = matches.get_one("destination").unwrap();
+    let file = load_config(matches)?.database;
+
+    let arg = |id| matches.get_one::<String>(id).cloned();
+
+    let location = match arg("database").or(file.endpoint) {
+        Some(endpoint) => DbLocation::from_endpoint(&endpoint)?,
+        None => {
+            let location = DbLocation::default_in(&data_dir(matches)?);
+
+            // Older versions kept the database in the destination
+            if let Some(old) = location.path().and_then(Path::file_name) {
+                let old = Path::new(dest).join(old);
+                if old.exists() {
+                    warn!(
+                        "Found a database of an older version at {}; move it to {} or pass --database to keep using it",
+                        old.to_string_lossy(),
+                        location.path().unwrap().to_string_lossy()
+                    );
+                }
+            }
+
+            location
+        }
+    };
+
+    let credentials = match arg("db user").or(file.username) {
+        Some(username) => Some(Credentials {
+            level: match arg("db level") {
+                Some(level) => level.parse()?,
+                None => file.level.unwrap_or_default(),
+            },
+            username,
+            password: arg("db password")
+                .map(Secret::new)
+                .or(file.password)
+                .context("A database user needs a password")?,
+        }),
+        None => None,
+    };
+
+    Ok(DbConfig {
+        location,
+        namespace: arg("db namespace")
+            .or(file.namespace)
+            .unwrap_or_else(|| db::DEFAULT_NAMESPACE.to_string()),
+        database: arg("db name")
+            .or(file.database)
+            .unwrap_or_else(|| db::DEFAULT_NAMESPACE.to_string()),
+        credentials,
+    })
+}
+
+/// Creates the fetcher selected by the global arguments
+///
+/// Only commands which go online create it, so e.g. `--fetcher offline` without recordings doesn't
+/// break the commands working on the database.
+fn fetcher(matches: &ArgMatches) -> anyhow::Result<Arc<dyn Fetcher>> {
+    create_fetcher(
+        if matches.get_flag("use bypass") {
+            FetcherKind::Bypass
+        } else {
+            matches.get_one::<String>("fetcher").unwrap().parse()?
+        },
+        matches.get_one::<String>("recordings").map(Into::into),
+        matches
+            .get_one::<String>("bypass command")
+            .map(String::as_str),
+        &http_settings(matches)?,
+    )
+}
+
+pub async fn exec_cli(matches: ArgMatches, db: &Db) -> Result<(), anyhow::Error> {
     println!("{} {}", constants::NAME, constants::VERSION);
     println!("{}\n", constants::LICENSE);
 
@@ -135,6 +771,8 @@ pub async fn exec_cli(app: Command) -> Result<(), anyhow::Error> {
 
             // Call the download function
             downloader::download_from_urls(
+                db,
+                &fetcher(&matches)?,
                 sub_matches
                     .get_many::<String>("URL")
                     .unwrap()
@@ -144,30 +782,47 @@ pub async fn exec_cli(app: Command) -> Result<(), anyhow::Error> {
                 matches.get_count("v").into(),
                 matches.get_flag("json only"),
                 false,
-                #[cfg(feature = "python_ffi")]
-                matches.get_flag("use bypass"),
-                matches.get_flag("get comments"),
+                comment_options(&matches)?.as_ref(),
             )
             .await
         }
 
         Some("crawl") => {
             let sub_matches = matches.subcommand_matches("crawl").unwrap();
+            let url: &String = sub_matches.get_one("URL").unwrap();
 
             // Call the crawl function
-            downloader::crawl_download(
-                sub_matches.get_one("URL").cloned().unwrap(),
-                matches.get_one("destination").cloned().unwrap(),
-                matches.get_count("v").into(),
-                matches.get_flag("json only"),
-                (sub_matches.get_one("limit")).cloned().unwrap(),
-                (sub_matches.get_one("skip")).cloned().unwrap(),
-                sub_matches.get_flag("paging"),
-                (sub_matches.get_one("retries")).cloned().unwrap(),
-                sub_matches.get_flag("no-download"),
-                #[cfg(feature = "python_ffi")]
-                matches.get_flag("use bypass"),
-                matches.get_flag("get comments"),
+            exec_crawl(db, &fetcher(&matches)?, url, &matches, sub_matches, &[]).await
+        }
+
+        Some("search") => {
+            let sub_matches = matches.subcommand_matches("search").unwrap();
+
+            let terms = |id| {
+                sub_matches
+                    .get_many::<String>(id)
+                    .unwrap_or_default()
+                    .map(|term| term.parse())
+                    .collect::<anyhow::Result<Vec<SearchTerm>>>()
+            };
+            let include = terms("include")?;
+            let exclude = search::resolve_terms(db, &terms("exclude")?).await?;
+
+            let url = search::search_url(
+                db,
+                sub_matches.get_one::<String>("QUERY").map(String::as_str),
+                &include,
+            )
+            .await?;
+            println!("Searching {url}");
+
+            exec_crawl(
+                db,
+                &fetcher(&matches)?,
+                &url,
+                &matches,
+                sub_matches,
+                &exclude,
             )
             .await
         }
@@ -177,10 +832,237 @@ pub async fn exec_cli(app: Command) -> Result<(), anyhow::Error> {
             let path: String = sub_matches.get_one("jobs-file-location").cloned().unwrap();
             let verbosity = matches.get_count("v").into();
 
-            run_jobs_from_path(path.into(), verbosity).await
+            let dest: String = matches.get_one("destination").cloned().unwrap();
+
+            run_jobs_from_path(db, &fetcher(&matches)?, path.into(), &dest, verbosity).await
+        }
+
+        Some("jobs") => {
+            let sub_matches = matches.subcommand_matches("jobs").unwrap();
+
+            match sub_matches.subcommand() {
+                Some(("validate", validate_matches)) => {
+                    let path: String = validate_matches
+                        .get_one("jobs-file-location")
+                        .cloned()
+                        .unwrap();
+
+                    validate_jobs_file(path.into())
+                }
+                Some(("schema", schema_matches)) => write_jobs_schema(
+                    schema_matches
+                        .get_one::<String>("output")
+                        .cloned()
+                        .map(Into::into),
+                ),
+                _ => unreachable!("clap requires a jobs subcommand"),
+            }
+        }
+
+        Some("import-library") => {
+            let sub_matches = matches.subcommand_matches("import-library").unwrap();
+            let root: String = sub_matches.get_one("root").cloned().unwrap();
+
+            library::import_library(db, root.into()).await
+        }
+
+        Some("migrate") => {
+            let sub_matches = matches.subcommand_matches("migrate").unwrap();
+
+            let fetcher = sub_matches
+                .get_flag("allow-fetch")
+                .then(|| fetcher(&matches))
+                .transpose()?;
+
+            migrate::migrate_paths(
+                fetcher.as_deref(),
+                sub_matches
+                    .get_many::<String>("PATH")
+                    .unwrap()
+                    .map(Into::into)
+                    .collect(),
+            )
+            .await
+        }
+
+        Some("get-filters") => {
+            let dest: String = matches.get_one("destination").cloned().unwrap();
+
+            filters::get_filters(db, fetcher(&matches)?.as_ref(), &dest).await
+        }
+
+        Some("diff-filters") => {
+            let sub_matches = matches.subcommand_matches("diff-filters").unwrap();
+
+            filter_diff::diff_filter_files(
+                sub_matches.get_one::<String>("OLD").unwrap().into(),
+                sub_matches.get_one::<String>("NEW").unwrap().into(),
+                sub_matches.get_one::<String>("output").map(Into::into),
+            )
         }
 
-        Some("get-filters") => filters::get_filters().await,
+        Some("account") => {
+            let sub_matches = matches.subcommand_matches("account").unwrap();
+            let config = load_config(&matches)?.account;
+            let session = session_path(&matches)?;
+
+            match sub_matches.subcommand() {
+                Some(("login", login_matches)) => {
+                    let arg = |id| login_matches.get_one::<String>(id).cloned();
+
+                    let login = match arg("session-cookie") {
+                        Some(cookie) => Login::SessionCookie(cookie),
+                        None => Login::Credentials {
+                            username: arg("username").or(config.username).context(
+                                "Pass --username or set it in the [account] section of the config file",
+                            )?,
+                            password: arg("password")
+                                .map(Secret::new)
+                                .or(config.password)
+                                .context("Logging in needs a password")?,
+                        },
+                    };
+
+                    account::login(&http_settings(&matches)?, &session, login).await
+                }
+                Some(("logout", _)) => account::logout(&session),
+                Some(("status", _)) => account::status(fetcher(&matches)?.as_ref()).await,
+                Some((list, list_matches)) => {
+                    if http_settings(&matches)?.cookies.is_empty() {
+                        anyhow::bail!(
+                            "Log in first with `account login`, or pass the cookies of a logged in browser with --cookies"
+                        );
+                    }
+
+                    let url = match list {
+                        "favorites" => config.favorites_url,
+                        _ => config.bookmarks_url,
+                    }
+                    .with_context(|| {
+                        format!("Set {list}_url in the [account] section of the config file to the URL of the list")
+                    })?;
+
+                    let fetcher = fetcher(&matches)?;
+                    account::ensure_logged_in(fetcher.as_ref(), &url).await?;
+                    exec_crawl(db, &fetcher, &url, &matches, list_matches, &[]).await
+                }
+                None => unreachable!("clap requires an account subcommand"),
+            }
+        }
+
+        Some("export-comments") => {
+            let sub_matches = matches.subcommand_matches("export-comments").unwrap();
+
+            let options = comment_settings(&matches)?;
+            let fetcher = options
+                .archive_media
+                .then(|| fetcher(&matches))
+                .transpose()?;
+
+            comments::export_comments(
+                fetcher.as_deref(),
+                sub_matches
+                    .get_many::<String>("PATH")
+                    .unwrap()
+                    .map(Into::into)
+                    .collect(),
+                &options,
+            )
+            .await
+        }
+
+        Some("sync-comments") => {
+            let sub_matches = matches.subcommand_matches("sync-comments").unwrap();
+
+            comments::sync_comments(
+                db,
+                &fetcher(&matches)?,
+                sub_matches
+                    .get_many::<String>("POST")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                sub_matches.get_flag("full"),
+                *matches.get_one("comment concurrency").unwrap(),
+            )
+            .await
+        }
+
+        Some("search-local") => {
+            let sub_matches = matches.subcommand_matches("search-local").unwrap();
+
+            // The query may be given as one argument or as several words
+            let query = sub_matches
+                .get_many::<String>("QUERY")
+                .unwrap()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            text_search::search_local(db, &query, *sub_matches.get_one("limit").unwrap()).await
+        }
+
+        Some("list") => {
+            let sub_matches = matches.subcommand_matches("list").unwrap();
+
+            let parse_all = |id: &str| -> anyhow::Result<Vec<_>> {
+                sub_matches
+                    .get_many::<String>(id)
+                    .unwrap_or_default()
+                    .map(|term| term.parse())
+                    .collect()
+            };
+            let parse_date = |id: &str| {
+                sub_matches
+                    .get_one::<String>(id)
+                    .map(|date| query::parse_date(date))
+                    .transpose()
+            };
+
+            let library_query = LibraryQuery {
+                include: parse_all("include")?,
+                exclude: parse_all("exclude")?,
+                after: parse_date("after")?,
+                before: parse_date("before")?,
+                conditions: sub_matches
+                    .get_many::<String>("where")
+                    .unwrap_or_default()
+                    .map(|condition| condition.parse())
+                    .collect::<anyhow::Result<_>>()?,
+                sort: sub_matches.get_one::<String>("sort").unwrap().parse()?,
+                descending: sub_matches.get_flag("descending"),
+                limit: *sub_matches.get_one("limit").unwrap(),
+                format: sub_matches.get_one::<String>("format").unwrap().parse()?,
+                output: sub_matches.get_one::<String>("output").map(Into::into),
+            };
+
+            query::list_library(db, &library_query).await
+        }
+
+        Some("stats") => {
+            let sub_matches = matches.subcommand_matches("stats").unwrap();
+
+            match sub_matches.subcommand() {
+                Some(("history", history_matches)) => {
+                    let post: &String = history_matches.get_one("POST").unwrap();
+                    let metric = history_matches.get_one::<String>("by").unwrap().parse()?;
+
+                    stats::print_history(db, post, metric).await
+                }
+                Some(("rank", rank_matches)) => {
+                    let metric = rank_matches.get_one::<String>("by").unwrap().parse()?;
+
+                    stats::print_ranking(
+                        db,
+                        metric,
+                        *rank_matches.get_one("days").unwrap(),
+                        *rank_matches.get_one("limit").unwrap(),
+                    )
+                    .await
+                }
+                _ => unreachable!("clap requires a stats subcommand"),
+            }
+        }
 
         _ => {
             println!("Something went very wrong");
@@ -1,15 +1,20 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::Arc, time::Duration};
 
+use anyhow::Context;
 use html_escape::decode_html_entities;
 use lazy_static::lazy_static;
 use regex::Regex;
-use reqwest::Client;
 use str_overlap::Overlap;
-use tracing::{info, info_span};
-
-use crate::data::{
-    ApiViewResponse, Comment, Comments, InfoboxRow, Post, PostBuf, RawInfoBoxRow, ResPage, TagLike,
-    TagLikeBuf,
+use tokio::{sync::Semaphore, task::JoinSet};
+use tracing::{info, info_span, warn};
+
+use crate::{
+    constants,
+    data::{
+        ApiViewResponse, Comment, Comments, InfoboxRow, Post, PostBuf, RawInfoBoxRow, ResPage,
+        TagLike, TagLikeBuf,
+    },
+    fetcher::Fetcher,
 };
 
 // Artist
@@ -95,51 +100,148 @@ pub fn extract_image_urls(text: &str) -> Vec<&str> {
 /// Downloads views, likes, dislikes, favourites, and the post id from the API
 ///
 /// The `url` must be the exact post url, as it's set in the `Referer` header to select the desired post
-pub async fn get_api_view(http_client: &Client, url: &str) -> reqwest::Result<ApiViewResponse> {
+pub async fn get_api_view(fetcher: &dyn Fetcher, url: &str) -> anyhow::Result<ApiViewResponse> {
     info!("Getting API info from URL {url}");
-    Ok(http_client
-        .get("https://hdporncomics.com/?rest_route=%2Fapi%2Fv1%2Fview")
-        .header("Referer", url)
-        .send()
-        .await?
-        .json()
-        .await
-        .unwrap())
-}
+    let text = fetcher
+        .get_api(
+            "https://hdporncomics.com/?rest_route=%2Fapi%2Fv1%2Fview",
+            Some(url),
+        )
+        .await?;
 
-pub async fn get_comments(post_id: u64, client: &Client) -> reqwest::Result<Vec<Comment>> {
-    info_span!("Downloading comments");
+    serde_json::from_str(&text)
+        .with_context(|| format!("Unexpected response from the view API for {url}: {text}"))
+}
 
-    let res: Comments = client
-        .get(format!(
-            "https://hdporncomics.com/wp-json/api/v1/comments/{post_id}?page_no=1"
-        ))
-        .send()
-        .await?
-        .json()
+/// Gets one page of the comments of a post, which also tells the number of pages
+pub async fn get_comments_page(
+    post_id: u64,
+    page: i64,
+    fetcher: &dyn Fetcher,
+) -> anyhow::Result<Comments> {
+    let text = fetcher
+        .get_api(
+            &format!("https://hdporncomics.com/wp-json/api/v1/comments/{post_id}?page_no={page}"),
+            None,
+        )
         .await?;
+    let res = serde_json::from_str(&text)
+        .with_context(|| format!("Unexpected response for comments page {page}: {text}"))?;
+
+    info!("Got comments page {page}");
+
+    Ok(res)
+}
+
+/// Gets one page of comments, retrying with a growing delay if it fails
+async fn get_comments_page_retrying(
+    post_id: u64,
+    page: i64,
+    fetcher: &dyn Fetcher,
+) -> anyhow::Result<Comments> {
+    let mut attempt = 1;
+
+    loop {
+        match get_comments_page(post_id, page, fetcher).await {
+            Ok(res) => return Ok(res),
+            Err(e) if attempt < constants::COMMENT_PAGE_ATTEMPTS => {
+                warn!("Getting comments page {page} failed (attempt {attempt}): {e:#}");
+                tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Gets several pages of comments with at most `concurrency` requests at a time, in the order of `pages`
+pub async fn get_comment_pages(
+    post_id: u64,
+    pages: &[i64],
+    fetcher: &Arc<dyn Fetcher>,
+    concurrency: usize,
+) -> anyhow::Result<Vec<Comments>> {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for (index, page) in pages.iter().copied().enumerate() {
+        let semaphore = semaphore.clone();
+        let fetcher = fetcher.clone();
+
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (
+                index,
+                page,
+                get_comments_page_retrying(post_id, page, fetcher.as_ref()).await,
+            )
+        });
+    }
+
+    let mut results: Vec<Option<Comments>> = pages.iter().map(|_| None).collect();
+    let mut failed = vec![];
+
+    while let Some(joined) = tasks.join_next().await {
+        match joined? {
+            (index, _, Ok(res)) => results[index] = Some(res),
+            (_, page, Err(e)) => failed.push((page, e)),
+        }
+    }
 
-    info!("Got comments page 1");
+    if !failed.is_empty() {
+        failed.sort_by_key(|(page, _)| *page);
+        let pages: Vec<_> = failed.iter().map(|(page, _)| page.to_string()).collect();
+        anyhow::bail!(
+            "Getting the comment pages {} failed: {:#}",
+            pages.join(", "),
+            failed[0].1
+        );
+    }
+
+    Ok(results.into_iter().flatten().collect())
+}
+
+pub async fn get_comments(
+    post_id: u64,
+    fetcher: &Arc<dyn Fetcher>,
+    concurrency: usize,
+) -> anyhow::Result<Vec<Comment>> {
+    info_span!("Downloading comments");
 
+    let res = get_comments_page_retrying(post_id, 1, fetcher.as_ref()).await?;
     let mut comments = res.comments;
 
-    for i in 2..=res.total_pages {
-        let mut res: Comments = client
-            .get(format!(
-                "https://hdporncomics.com/wp-json/api/v1/comments/{post_id}?page_no={i}"
-            ))
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        info!("Got comments page {i}");
+    let pages: Vec<i64> = (2..=res.total_pages).collect();
+    for mut res in get_comment_pages(post_id, &pages, fetcher, concurrency).await? {
         comments.append(&mut res.comments);
     }
 
     Ok(comments)
 }
 
+/// Removes the HTML tags of e.g. comments, decodes their entities, and collapses whitespace
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    decode_html_entities(&text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 pub fn extract_title(text: &str) -> Option<Cow<str>> {
     info!("Extracting post title");
 
@@ -246,3 +348,86 @@ impl SuffixParse for &str {
         Ok(unwrap_wrap)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use async_trait::async_trait;
+    use reqwest::Client;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Answers later comment pages sooner and fails page 3 the first time it's requested
+    #[derive(Default)]
+    struct FlakyComments {
+        attempts: Mutex<HashMap<i64, u32>>,
+        client: Client,
+    }
+
+    #[async_trait]
+    impl Fetcher for FlakyComments {
+        async fn get_page(&self, url: &str) -> anyhow::Result<String> {
+            anyhow::bail!("Unexpected page request {url}")
+        }
+
+        async fn get_api(&self, url: &str, _referer: Option<&str>) -> anyhow::Result<String> {
+            let page: i64 = url.rsplit_once("page_no=").unwrap().1.parse()?;
+            let attempt = {
+                let mut attempts = self.attempts.lock().unwrap();
+                let attempt = attempts.entry(page).or_default();
+                *attempt += 1;
+                *attempt
+            };
+
+            tokio::time::sleep(Duration::from_millis(20 * (5 - page) as u64)).await;
+            if page == 3 && attempt == 1 {
+                anyhow::bail!("Connection reset");
+            }
+
+            Ok(json!({
+                "current_page": page,
+                "total_comments": 4,
+                "total_pages": 5,
+                "comments": [{
+                    "comment_ID": page.to_string(),
+                    "comment_post_ID": "1",
+                    "post_url": "https://hdporncomics.com/post/",
+                    "comment_author": "someone",
+                    "content": "<p>Hi</p>",
+                    "approved": "1",
+                    "comment_parrent": "0",
+                    "likes": 0,
+                    "posted_on": "2024-01-01",
+                    "user_id": "0",
+                    "profile_pic": "",
+                    "role": "",
+                    "children": null,
+                }],
+            })
+            .to_string())
+        }
+
+        fn client(&self) -> &Client {
+            &self.client
+        }
+    }
+
+    #[tokio::test]
+    async fn gets_comment_pages_in_order_and_retries_failures() {
+        let flaky = Arc::new(FlakyComments::default());
+        let fetcher: Arc<dyn Fetcher> = flaky.clone();
+
+        let res = get_comment_pages(1, &[2, 3, 4], &fetcher, 3).await.unwrap();
+
+        let pages: Vec<_> = res.iter().map(|res| res.current_page).collect();
+        assert_eq!(pages, [2, 3, 4]);
+        assert_eq!(res[1].comments[0].comment_id, "3");
+
+        let attempts = flaky.attempts.lock().unwrap();
+        assert_eq!(attempts[&2], 1);
+        assert_eq!(attempts[&3], 2);
+        assert_eq!(attempts[&4], 1);
+    }
+}
@@ -0,0 +1,482 @@
+use std::{cmp::Ordering, collections::BTreeMap, fs, io::Write, path::PathBuf, str::FromStr};
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::{
+    db::{self, Db},
+    filters::Taxonomy,
+    search::SearchTerm,
+};
+
+/// A numeric field of a listed post
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Views,
+    Upvotes,
+    Downvotes,
+    Favorites,
+    Pages,
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "views" => Ok(Field::Views),
+            "upvotes" | "likes" => Ok(Field::Upvotes),
+            "downvotes" | "dislikes" => Ok(Field::Downvotes),
+            "favorites" => Ok(Field::Favorites),
+            "pages" => Ok(Field::Pages),
+            _ => anyhow::bail!(
+                "Unknown field {s}; expected views, upvotes, downvotes, favorites, or pages"
+            ),
+        }
+    }
+}
+
+/// A condition on a numeric field, e.g. `pages>=20` or `views>1000`
+#[derive(Debug, Clone)]
+pub struct Condition {
+    field: Field,
+    ordering: &'static [Ordering],
+    value: u64,
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Two-character operators have to be checked first
+        const OPERATORS: [(&str, &[Ordering]); 6] = [
+            (">=", &[Ordering::Greater, Ordering::Equal]),
+            ("<=", &[Ordering::Less, Ordering::Equal]),
+            ("!=", &[Ordering::Greater, Ordering::Less]),
+            (">", &[Ordering::Greater]),
+            ("<", &[Ordering::Less]),
+            ("=", &[Ordering::Equal]),
+        ];
+
+        let (field, ordering, value) = OPERATORS
+            .iter()
+            .find_map(|(operator, ordering)| {
+                s.split_once(operator)
+                    .map(|(field, value)| (field, *ordering, value))
+            })
+            .with_context(|| format!("Expected a condition like \"pages>=20\", got \"{s}\""))?;
+
+        Ok(Self {
+            field: field.parse()?,
+            ordering,
+            value: value
+                .trim()
+                .parse()
+                .with_context(|| format!("Expected a number in \"{s}\""))?,
+        })
+    }
+}
+
+impl Condition {
+    /// Posts with an unknown value never match
+    fn matches(&self, post: &ListedPost) -> bool {
+        post.field(self.field)
+            .is_some_and(|value| self.ordering.contains(&value.cmp(&self.value)))
+    }
+}
+
+/// What to sort the listed posts by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Title,
+    Date,
+    Field(Field),
+}
+
+impl SortKey {
+    pub const NAMES: [&'static str; 7] = [
+        "title",
+        "date",
+        "views",
+        "upvotes",
+        "downvotes",
+        "favorites",
+        "pages",
+    ];
+}
+
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "title" => Ok(SortKey::Title),
+            "date" => Ok(SortKey::Date),
+            field => Ok(SortKey::Field(field.parse()?)),
+        }
+    }
+}
+
+/// How to print the listed posts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 3] = ["table", "json", "csv"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => anyhow::bail!("Unknown format {s}; expected table, json, or csv"),
+        }
+    }
+}
+
+/// Parses a date like `2024-01-31` (midnight UTC) or an RFC 3339 timestamp
+pub fn parse_date(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .with_context(|| format!("Expected a date like 2024-01-31, got \"{s}\""))?;
+
+    Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+}
+
+/// The filters, order, and format of a library listing
+#[derive(Debug, Clone)]
+pub struct LibraryQuery {
+    pub include: Vec<SearchTerm>,
+    pub exclude: Vec<SearchTerm>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    pub conditions: Vec<Condition>,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub limit: usize,
+    pub format: OutputFormat,
+
+    /// Writes the JSON or CSV to this file instead of stdout
+    pub output: Option<PathBuf>,
+}
+
+/// A downloaded post with its terms and page count
+#[derive(Debug, Serialize)]
+struct ListedPost {
+    id: String,
+    title: String,
+    source_url: String,
+    download_date: String,
+    #[serde(skip)]
+    date: Option<DateTime<Utc>>,
+    folder: String,
+    views: Option<u32>,
+    upvotes: Option<u32>,
+    downvotes: Option<u32>,
+    favorites: Option<u32>,
+    pages: usize,
+    terms: BTreeMap<Taxonomy, Vec<String>>,
+    #[serde(skip)]
+    tags: BTreeMap<Taxonomy, Vec<String>>,
+}
+
+impl ListedPost {
+    fn field(&self, field: Field) -> Option<u64> {
+        match field {
+            Field::Views => self.views.map(u64::from),
+            Field::Upvotes => self.upvotes.map(u64::from),
+            Field::Downvotes => self.downvotes.map(u64::from),
+            Field::Favorites => self.favorites.map(u64::from),
+            Field::Pages => Some(self.pages as u64),
+        }
+    }
+
+    /// Whether the post has a term matching `term` by name (case-insensitive) or tag
+    fn has_term(&self, term: &SearchTerm) -> bool {
+        let name = term.name.to_lowercase();
+        let names = self.terms.get(&term.taxonomy).into_iter().flatten();
+        let tags = self.tags.get(&term.taxonomy).into_iter().flatten();
+
+        names
+            .map(|name| name.to_lowercase())
+            .chain(tags.cloned())
+            .any(|candidate| candidate == name)
+    }
+
+    fn compare(&self, other: &Self, key: SortKey) -> Ordering {
+        match key {
+            SortKey::Title => self.title.to_lowercase().cmp(&other.title.to_lowercase()),
+            SortKey::Date => self.date.cmp(&other.date),
+            SortKey::Field(field) => self.field(field).cmp(&other.field(field)),
+        }
+    }
+}
+
+/// Lists the downloaded posts matching `query`
+pub async fn list_library(db: &Db, query: &LibraryQuery) -> anyhow::Result<()> {
+    let mut page_counts = db::page_counts(db).await?;
+
+    let mut posts: BTreeMap<_, _> = db::downloaded_posts(db)
+        .await?
+        .into_iter()
+        .map(|post| {
+            let id = post.id.to_string();
+            let listed = ListedPost {
+                id: id.clone(),
+                date: DateTime::parse_from_rfc3339(&post.download_date)
+                    .ok()
+                    .map(|date| date.with_timezone(&Utc)),
+                title: post.title,
+                source_url: post.source_url,
+                download_date: post.download_date,
+                folder: post.folder,
+                views: post.views,
+                upvotes: post.upvotes,
+                downvotes: post.downvotes,
+                favorites: post.favorites,
+                pages: page_counts.remove(&id).unwrap_or(0),
+                terms: BTreeMap::new(),
+                tags: BTreeMap::new(),
+            };
+
+            (id, listed)
+        })
+        .collect();
+
+    for term in db::post_terms(db).await? {
+        if let Some(post) = posts.get_mut(&term.post.to_string()) {
+            post.terms.entry(term.taxonomy).or_default().push(term.name);
+            post.tags.entry(term.taxonomy).or_default().push(term.tag);
+        }
+    }
+
+    let mut posts: Vec<_> = posts
+        .into_values()
+        .filter(|post| query.include.iter().all(|term| post.has_term(term)))
+        .filter(|post| !query.exclude.iter().any(|term| post.has_term(term)))
+        .filter(|post| {
+            query
+                .conditions
+                .iter()
+                .all(|condition| condition.matches(post))
+        })
+        // Posts without a valid date can't be in a date range
+        .filter(|post| {
+            query
+                .after
+                .is_none_or(|after| post.date.is_some_and(|d| d >= after))
+        })
+        .filter(|post| {
+            query
+                .before
+                .is_none_or(|before| post.date.is_some_and(|d| d < before))
+        })
+        .collect();
+
+    posts.sort_by(|a, b| {
+        let ordering = a.compare(b, query.sort);
+        if query.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+
+    if query.limit != 0 {
+        posts.truncate(query.limit);
+    }
+
+    let serialized = match query.format {
+        OutputFormat::Table => {
+            print_table(&posts);
+            return Ok(());
+        }
+        OutputFormat::Json => serde_json::to_vec_pretty(&posts)?,
+        OutputFormat::Csv => write_csv(&posts)?,
+    };
+
+    match &query.output {
+        Some(path) => {
+            fs::write(path, serialized).context("Failed to write the listing")?;
+            println!(
+                "Wrote {} post(s) to \"{}\"",
+                posts.len(),
+                path.to_string_lossy()
+            );
+        }
+        None => std::io::stdout().write_all(&serialized)?,
+    }
+
+    Ok(())
+}
+
+fn format_count(count: Option<u32>) -> String {
+    count
+        .map(|count| count.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn print_table(posts: &[ListedPost]) {
+    println!(
+        "{:<10} {:>9} {:>7} {:>5} {:>5} {:>5}  {:<20} title",
+        "date", "views", "up", "down", "fav", "pages", "artist"
+    );
+
+    for post in posts {
+        let date = post
+            .date
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let artists = post
+            .terms
+            .get(&Taxonomy::Artist)
+            .map(|artists| artists.join(", "))
+            .unwrap_or_default();
+
+        println!(
+            "{date:<10} {:>9} {:>7} {:>5} {:>5} {:>5}  {artists:<20} {}",
+            format_count(post.views),
+            format_count(post.upvotes),
+            format_count(post.downvotes),
+            format_count(post.favorites),
+            post.pages,
+            post.title,
+        );
+    }
+
+    println!("\n{} post(s)", posts.len());
+}
+
+fn write_csv(posts: &[ListedPost]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+
+    let mut header = vec![
+        "id",
+        "title",
+        "source_url",
+        "download_date",
+        "folder",
+        "views",
+        "upvotes",
+        "downvotes",
+        "favorites",
+        "pages",
+    ];
+    header.extend(Taxonomy::ALL.iter().map(|taxonomy| taxonomy.as_str()));
+    writer.write_record(&header)?;
+
+    let count = |count: Option<u32>| count.map(|count| count.to_string()).unwrap_or_default();
+
+    for post in posts {
+        let mut record = vec![
+            post.id.clone(),
+            post.title.clone(),
+            post.source_url.clone(),
+            post.download_date.clone(),
+            post.folder.clone(),
+            count(post.views),
+            count(post.upvotes),
+            count(post.downvotes),
+            count(post.favorites),
+            post.pages.to_string(),
+        ];
+
+        // Multiple terms are separated by semicolons, as names may contain commas
+        record.extend(Taxonomy::ALL.iter().map(|taxonomy| {
+            post.terms
+                .get(taxonomy)
+                .map(|terms| terms.join("; "))
+                .unwrap_or_default()
+        }));
+
+        writer.write_record(&record)?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(views: Option<u32>, pages: usize) -> ListedPost {
+        ListedPost {
+            id: "post:1".to_string(),
+            title: "Title".to_string(),
+            source_url: "https://hdporncomics.com/title/".to_string(),
+            download_date: "2024-01-31T00:00:00Z".to_string(),
+            date: None,
+            folder: "title".to_string(),
+            views,
+            upvotes: None,
+            downvotes: None,
+            favorites: None,
+            pages,
+            terms: BTreeMap::new(),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    fn condition(s: &str) -> Condition {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_two_character_operators_first() {
+        let parsed = condition("pages>=20");
+        assert_eq!(parsed.field, Field::Pages);
+        assert_eq!(parsed.ordering, &[Ordering::Greater, Ordering::Equal]);
+        assert_eq!(parsed.value, 20);
+
+        let parsed = condition(" Likes != 5 ");
+        assert_eq!(parsed.field, Field::Upvotes);
+        assert_eq!(parsed.ordering, &[Ordering::Greater, Ordering::Less]);
+        assert_eq!(parsed.value, 5);
+    }
+
+    #[test]
+    fn matches_by_operator() {
+        let post = post(Some(1000), 20);
+
+        for (s, expected) in [
+            ("pages>=20", true),
+            ("pages>20", false),
+            ("pages<=20", true),
+            ("pages<20", false),
+            ("pages=20", true),
+            ("pages!=20", false),
+            ("views>999", true),
+            ("views<1000", false),
+        ] {
+            assert_eq!(condition(s).matches(&post), expected, "{s}");
+        }
+    }
+
+    #[test]
+    fn unknown_values_never_match() {
+        let post = post(None, 0);
+
+        assert!(!condition("views<10").matches(&post));
+        assert!(!condition("views!=10").matches(&post));
+        assert!(condition("pages=0").matches(&post));
+    }
+
+    #[test]
+    fn rejects_invalid_conditions() {
+        for s in ["pages", "pages>many", "size>3", "views>-1", ">5"] {
+            assert!(s.parse::<Condition>().is_err(), "{s}");
+        }
+    }
+}
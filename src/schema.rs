@@ -0,0 +1,136 @@
+use anyhow::Context;
+
+use crate::{constants, db::Db};
+
+/// The migration scripts in the order they have to be applied; the schema version is the number of applied scripts
+///
+/// Released scripts must never change, as they won't be applied again; add a new one instead.
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("initial", include_str!("./schema/0001_initial.surql")),
+    ("post stats", include_str!("./schema/0002_post_stats.surql")),
+    (
+        "full-text search",
+        include_str!("./schema/0003_full_text_search.surql"),
+    ),
+    (
+        "comment changes",
+        include_str!("./schema/0004_comment_changes.surql"),
+    ),
+];
+
+/// The schema version this program understands
+pub fn latest_version() -> usize {
+    MIGRATIONS.len()
+}
+
+/// Gets the version of the schema stored in the database, which is 0 for new databases
+pub async fn current_version(db: &Db) -> anyhow::Result<usize> {
+    let version: Option<usize> = db
+        .query("SELECT VALUE version FROM migration ORDER BY version DESC LIMIT 1")
+        .await?
+        .take(0)?;
+
+    Ok(version.unwrap_or(0))
+}
+
+/// Applies all migrations the database is missing, each in its own transaction
+///
+/// Fails if the database was migrated by a newer version of this program.
+pub async fn migrate(db: &Db) -> anyhow::Result<()> {
+    let current = current_version(db).await?;
+    let latest = latest_version();
+
+    if current > latest {
+        anyhow::bail!(
+            "The database schema has version {current}, but this program only understands up to version {latest}; please update {}",
+            constants::NAME
+        );
+    }
+
+    for (index, (name, script)) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = index + 1;
+
+        db.query("BEGIN TRANSACTION")
+            .query(*script)
+            .query(
+                "CREATE type::thing('migration', $version) SET
+                    version = $version,
+                    name = $name,
+                    program_version = $program_version,
+                    applied = time::now()",
+            )
+            .query("COMMIT TRANSACTION")
+            .bind(("version", version))
+            .bind(("name", name))
+            .bind(("program_version", constants::VERSION))
+            .await?
+            .check()
+            .with_context(|| {
+                format!("Failed to apply the database migration {version} ({name})")
+            })?;
+
+        // New databases are set up silently
+        if current > 0 {
+            println!("Migrated the database schema to version {version} ({name})");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::memory_db;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct AppliedMigration {
+        version: usize,
+        name: String,
+        applied: i64,
+    }
+
+    #[tokio::test]
+    async fn applies_migrations_in_order() {
+        let db = memory_db().await;
+
+        let applied: Vec<AppliedMigration> = db
+            .query("SELECT version, name, time::nano(applied) AS applied FROM migration ORDER BY version")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        let versions: Vec<_> = applied.iter().map(|migration| migration.version).collect();
+        assert_eq!(versions, (1..=latest_version()).collect::<Vec<_>>());
+
+        let names: Vec<_> = applied
+            .iter()
+            .map(|migration| migration.name.as_str())
+            .collect();
+        let expected: Vec<_> = MIGRATIONS.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, expected);
+
+        assert!(applied
+            .windows(2)
+            .all(|pair| pair[0].applied <= pair[1].applied));
+
+        // Migrating an up to date database changes nothing
+        migrate(&db).await.unwrap();
+        assert_eq!(current_version(&db).await.unwrap(), latest_version());
+    }
+
+    #[tokio::test]
+    async fn refuses_newer_schemas() {
+        let db = memory_db().await;
+        db.query("CREATE type::thing('migration', $version) SET version = $version")
+            .bind(("version", latest_version() + 1))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let error = migrate(&db).await.unwrap_err();
+        assert!(error.to_string().contains("please update"));
+    }
+}
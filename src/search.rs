@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::Context;
+
+use crate::{
+    constants,
+    data::PostBuf,
+    db::{self, Db},
+    filters::{FilterValue, Taxonomy},
+};
+
+/// A filter given as `taxonomy:name`, e.g. `artist:Some Artist` or `tag:big-tits`
+#[derive(Debug, Clone)]
+pub struct SearchTerm {
+    pub taxonomy: Taxonomy,
+    pub name: String,
+}
+
+impl FromStr for SearchTerm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (taxonomy, name) = s
+            .split_once(':')
+            .with_context(|| format!("Expected \"taxonomy:name\", got \"{s}\""))?;
+
+        Ok(Self {
+            taxonomy: taxonomy.trim().parse()?,
+            name: name.trim().to_string(),
+        })
+    }
+}
+
+/// Finds the stored term matching `term` by its name (case-insensitive) or its tag
+pub async fn resolve_term(db: &Db, term: &SearchTerm) -> anyhow::Result<FilterValue> {
+    let terms = db::taxonomy_terms(db, term.taxonomy).await?;
+
+    if terms.is_empty() {
+        anyhow::bail!(
+            "No {} filters are stored; run get-filters first",
+            term.taxonomy
+        );
+    }
+
+    let name = term.name.to_lowercase();
+    let mut matches: Vec<_> = terms
+        .iter()
+        .filter(|value| value.name.to_lowercase() == name || value.tag == name)
+        .collect();
+
+    match matches.len() {
+        1 => Ok(matches.remove(0).clone()),
+        0 => {
+            let similar: Vec<_> = terms
+                .iter()
+                .filter(|value| value.name.to_lowercase().contains(&name))
+                .take(5)
+                .map(|value| format!("\"{}\"", value.name))
+                .collect();
+
+            if similar.is_empty() {
+                anyhow::bail!("No {} named \"{}\"", term.taxonomy, term.name)
+            } else {
+                anyhow::bail!(
+                    "No {} named \"{}\"; did you mean {}?",
+                    term.taxonomy,
+                    term.name,
+                    similar.join(", ")
+                )
+            }
+        }
+        _ => anyhow::bail!(
+            "\"{}\" matches {} {} terms; use the tag instead: {}",
+            term.name,
+            matches.len(),
+            term.taxonomy,
+            matches
+                .iter()
+                .map(|value| value.tag.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Collects the tags of the terms by their taxonomy
+fn group_tags(terms: &[(Taxonomy, FilterValue)]) -> BTreeMap<Taxonomy, Vec<&str>> {
+    let mut groups: BTreeMap<Taxonomy, Vec<&str>> = BTreeMap::new();
+    for (taxonomy, value) in terms {
+        groups.entry(*taxonomy).or_default().push(&value.tag);
+    }
+
+    groups
+}
+
+/// The WordPress query variable selecting posts by the terms of a taxonomy
+///
+/// Custom taxonomies are queried by their name, but the built-in categories by `category_name`.
+fn query_var(taxonomy: Taxonomy) -> &'static str {
+    match taxonomy {
+        Taxonomy::Category => "category_name",
+        other => other.as_str(),
+    }
+}
+
+/// Builds the URL of the listing page of all posts matching the text and the filters
+///
+/// Terms of a taxonomy are joined with a literal `+`, which WordPress reads as "all of them".
+/// WordPress has no query variable excluding terms, so excluded terms are checked with [`is_excluded`].
+pub fn build_search_url(
+    text: Option<&str>,
+    include: &[(Taxonomy, FilterValue)],
+) -> anyhow::Result<String> {
+    let encode =
+        |value: &str| form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+
+    let mut query = vec![];
+    if let Some(text) = text.map(str::trim).filter(|text| !text.is_empty()) {
+        query.push(format!("s={}", encode(text)));
+    }
+    for (taxonomy, tags) in group_tags(include) {
+        let tags: Vec<_> = tags.into_iter().map(encode).collect();
+        query.push(format!("{}={}", query_var(taxonomy), tags.join("+")));
+    }
+
+    let mut url = reqwest::Url::parse(constants::SEARCH_PATH)?;
+    if !query.is_empty() {
+        url.set_query(Some(&query.join("&")));
+    }
+
+    Ok(url.to_string())
+}
+
+/// The classes WordPress gives the posts with a term, e.g. `artist-some-artist`
+///
+/// Built-in categories and tags are prefixed with `category-` and `tag-`, other taxonomies with their name.
+fn post_classes(taxonomy: Taxonomy, tag: &str) -> Vec<String> {
+    match taxonomy {
+        Taxonomy::Category => vec![format!("category-{tag}")],
+        Taxonomy::Tags => vec![format!("tag-{tag}"), format!("tags-{tag}")],
+        other => vec![format!("{other}-{tag}")],
+    }
+}
+
+/// Whether a crawled post has any of the excluded terms, judged by its classes and the terms shown with it
+pub fn is_excluded(post: &PostBuf, exclude: &[(Taxonomy, FilterValue)]) -> bool {
+    exclude.iter().any(|(taxonomy, value)| {
+        let classes = post_classes(*taxonomy, &value.tag);
+
+        post.meta_tags.iter().any(|class| classes.contains(class))
+            || post.rendered_tags.iter().any(|tag| {
+                db::term_of_href(&tag.href)
+                    .is_some_and(|(found, slug)| found == *taxonomy && slug == value.tag)
+            })
+    })
+}
+
+/// Resolves all terms by name
+pub async fn resolve_terms(
+    db: &Db,
+    terms: &[SearchTerm],
+) -> anyhow::Result<Vec<(Taxonomy, FilterValue)>> {
+    let mut resolved = vec![];
+    for term in terms {
+        resolved.push((term.taxonomy, resolve_term(db, term).await?));
+    }
+
+    Ok(resolved)
+}
+
+/// Resolves all terms by name and builds the URL of the matching listing page
+pub async fn search_url(
+    db: &Db,
+    text: Option<&str>,
+    include: &[SearchTerm],
+) -> anyhow::Result<String> {
+    let include = resolve_terms(db, include).await?;
+
+    build_search_url(text, &include)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::TagLikeBuf;
+
+    fn term(taxonomy: Taxonomy, tag: &str) -> (Taxonomy, FilterValue) {
+        let value = FilterValue {
+            name: tag.to_string(),
+            id: 1,
+            tag: tag.to_string(),
+        };
+
+        (taxonomy, value)
+    }
+
+    #[test]
+    fn joins_the_terms_of_a_taxonomy_with_a_literal_plus() {
+        let url = build_search_url(
+            Some("big ship"),
+            &[
+                term(Taxonomy::Tags, "glasses"),
+                term(Taxonomy::Artist, "some-artist"),
+                term(Taxonomy::Tags, "big-tits"),
+                term(Taxonomy::Category, "western"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            url,
+            "https://hdporncomics.com/?s=big+ship&artist=some-artist&category_name=western&tags=glasses+big-tits"
+        );
+    }
+
+    fn crawled(meta_tags: &[&str], rendered_tags: &[&str]) -> PostBuf {
+        PostBuf {
+            post_id: 1,
+            name: "Title".to_string(),
+            url: "https://hdporncomics.com/title/".to_string(),
+            views: 0,
+            upvotes: 0,
+            downvotes: 0,
+            meta_tags: meta_tags.iter().map(|class| class.to_string()).collect(),
+            rendered_tags: rendered_tags
+                .iter()
+                .map(|href| TagLikeBuf {
+                    href: href.to_string(),
+                    text: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn excludes_posts_by_their_classes() {
+        let exclude = [
+            term(Taxonomy::Tags, "glasses"),
+            term(Taxonomy::Category, "western"),
+            term(Taxonomy::Artist, "some-artist"),
+        ];
+
+        for class in [
+            "tag-glasses",
+            "tags-glasses",
+            "category-western",
+            "artist-some-artist",
+        ] {
+            assert!(
+                is_excluded(&crawled(&["post", class], &[]), &exclude),
+                "{class}"
+            );
+        }
+
+        assert!(!is_excluded(
+            &crawled(&["tag-glasses-free", "parody-glasses"], &[]),
+            &exclude
+        ));
+        assert!(!is_excluded(&crawled(&["tag-glasses"], &[]), &[]));
+    }
+
+    #[test]
+    fn excludes_posts_by_their_shown_terms() {
+        let exclude = [term(Taxonomy::Parody, "some-parody")];
+
+        assert!(is_excluded(
+            &crawled(&[], &["https://hdporncomics.com/parody/Some-Parody/"]),
+            &exclude
+        ));
+        assert!(!is_excluded(
+            &crawled(&[], &["https://hdporncomics.com/tags/some-parody/"]),
+            &exclude
+        ));
+    }
+
+    #[test]
+    fn leaves_out_empty_text() {
+        assert_eq!(
+            build_search_url(Some("  "), &[term(Taxonomy::Parody, "a&b")]).unwrap(),
+            "https://hdporncomics.com/?parody=a%26b"
+        );
+        assert_eq!(
+            build_search_url(None, &[]).unwrap(),
+            "https://hdporncomics.com/"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::{collections::HashMap, str::FromStr};
+
+use chrono::{Duration, Utc};
+
+use crate::db::{self, Db, StatsSnapshot};
+
+/// The width of the longest bar of a chart
+const CHART_WIDTH: usize = 40;
+
+/// A statistic of a post which is tracked over time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Views,
+    Upvotes,
+    Downvotes,
+    Favorites,
+}
+
+impl Metric {
+    pub const NAMES: [&'static str; 4] = ["views", "upvotes", "downvotes", "favorites"];
+
+    fn of(self, snapshot: &StatsSnapshot) -> Option<u32> {
+        match self {
+            Metric::Views => snapshot.views,
+            Metric::Upvotes => snapshot.upvotes,
+            Metric::Downvotes => snapshot.downvotes,
+            Metric::Favorites => snapshot.favorites,
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "views" => Ok(Metric::Views),
+            "upvotes" | "likes" => Ok(Metric::Upvotes),
+            "downvotes" | "dislikes" => Ok(Metric::Downvotes),
+            "favorites" => Ok(Metric::Favorites),
+            _ => anyhow::bail!(
+                "Unknown metric {s}; expected one of {}",
+                Metric::NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+fn format_count(count: Option<u32>) -> String {
+    count
+        .map(|count| count.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Prints every observation of a post with a bar chart of `metric`
+pub async fn print_history(db: &Db, post: &str, metric: Metric) -> anyhow::Result<()> {
+    let thing = db::find_post(db, post).await?;
+    let history = db::stats_history(db, &thing).await?;
+
+    let Some(first) = history.first() else {
+        println!("No stats of {thing} are stored yet.");
+        return Ok(());
+    };
+
+    if let Some(title) = &first.title {
+        println!("{title} ({thing})\n");
+    }
+
+    let max = history
+        .iter()
+        .filter_map(|snapshot| metric.of(snapshot))
+        .max()
+        .unwrap_or(0);
+
+    println!(
+        "{:<25} {:>9} {:>9} {:>9} {:>9}",
+        "date", "views", "upvotes", "downvotes", "favorites"
+    );
+
+    for snapshot in history.iter() {
+        let bar = match metric.of(snapshot) {
+            Some(count) if max > 0 => "#".repeat(count as usize * CHART_WIDTH / max as usize),
+            _ => String::new(),
+        };
+
+        println!(
+            "{:<25} {:>9} {:>9} {:>9} {:>9} {bar}",
+            snapshot.date.format("%Y-%m-%d %H:%M:%S UTC"),
+            format_count(snapshot.views),
+            format_count(snapshot.upvotes),
+            format_count(snapshot.downvotes),
+            format_count(snapshot.favorites),
+        );
+    }
+
+    Ok(())
+}
+
+/// How a statistic of a post changed between its first and last observation
+#[derive(Debug, PartialEq, Eq)]
+struct Growth {
+    title: Option<String>,
+    first: u32,
+    last: u32,
+}
+
+impl Growth {
+    fn growth(&self) -> i64 {
+        i64::from(self.last) - i64::from(self.first)
+    }
+}
+
+/// The growth of `metric` of every post with at least one observation of it, from snapshots ordered by date
+fn growth_by_post(snapshots: &[StatsSnapshot], metric: Metric) -> HashMap<String, Growth> {
+    let mut posts: HashMap<String, Growth> = HashMap::new();
+
+    for snapshot in snapshots.iter() {
+        let Some(count) = metric.of(snapshot) else {
+            continue;
+        };
+
+        posts
+            .entry(snapshot.post.to_string())
+            .and_modify(|growth| growth.last = count)
+            .or_insert(Growth {
+                title: snapshot.title.clone(),
+                first: count,
+                last: count,
+            });
+    }
+
+    posts
+}
+
+/// Prints the posts whose `metric` grew the most within the last `days` days
+///
+/// Growth is the difference between the first and the last observation in that time,
+/// so posts need at least two observations to be ranked. A `limit` of 0 prints all posts.
+pub async fn print_ranking(db: &Db, metric: Metric, days: i64, limit: usize) -> anyhow::Result<()> {
+    let since = Utc::now() - Duration::days(days);
+    let snapshots = db::stats_since(db, since).await?;
+
+    let mut ranking: Vec<_> = growth_by_post(&snapshots, metric)
+        .into_iter()
+        .map(|(post, growth)| {
+            (
+                growth.growth(),
+                post,
+                growth.title,
+                growth.first,
+                growth.last,
+            )
+        })
+        .filter(|(growth, ..)| *growth != 0)
+        .collect();
+    ranking.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    if ranking.is_empty() {
+        println!("No post has changed in the last {days} days.");
+        return Ok(());
+    }
+
+    for (rank, (growth, post, title, first, last)) in ranking
+        .into_iter()
+        .take(if limit == 0 { usize::MAX } else { limit })
+        .enumerate()
+    {
+        println!(
+            "{:>3}. {growth:+} ({first} -> {last}) {} [{post}]",
+            rank + 1,
+            title.as_deref().unwrap_or("(untitled)"),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::sql::{Id, Thing};
+
+    use super::*;
+
+    fn snapshot(post: u64, day: u32, views: Option<u32>) -> StatsSnapshot {
+        StatsSnapshot {
+            post: Thing::from(("post", Id::from(post))),
+            title: Some(format!("Post {post}")),
+            date: format!("2024-01-{day:02}T00:00:00Z").parse().unwrap(),
+            views,
+            upvotes: None,
+            downvotes: None,
+            favorites: None,
+        }
+    }
+
+    #[test]
+    fn grows_from_the_first_to_the_last_observation() {
+        let snapshots = [
+            snapshot(1, 1, Some(10)),
+            snapshot(2, 1, Some(5)),
+            snapshot(3, 1, None),
+            snapshot(1, 2, None),
+            snapshot(1, 3, Some(25)),
+            snapshot(3, 3, None),
+        ];
+
+        let growth = growth_by_post(&snapshots, Metric::Views);
+
+        assert_eq!(growth["post:1"].growth(), 15);
+        assert_eq!(
+            growth["post:1"],
+            Growth {
+                title: Some("Post 1".to_string()),
+                first: 10,
+                last: 25,
+            }
+        );
+
+        // A single observation can't grow
+        assert_eq!(growth["post:2"].growth(), 0);
+
+        // Posts without counts aren't ranked at all
+        assert_eq!(growth.get("post:3"), None);
+        assert!(growth_by_post(&snapshots, Metric::Favorites).is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use reqwest::Client;
+
+use crate::{
+    db::{connect, Db, DbConfig, DbLocation, DEFAULT_NAMESPACE},
+    fetcher::Fetcher,
+};
+
+/// An empty folder for one test, named after it so parallel tests don't share one
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hdpc-dl-test-{name}-{}", std::process::id()));
+
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// A new in-memory database with the latest schema
+pub async fn memory_db() -> Db {
+    connect(&DbConfig {
+        location: DbLocation::Memory(None),
+        namespace: DEFAULT_NAMESPACE.to_string(),
+        database: DEFAULT_NAMESPACE.to_string(),
+        credentials: None,
+    })
+    .await
+    .unwrap()
+}
+
+/// Answers requests from fixed responses and remembers every request
+///
+/// API responses are looked up by `"<url> <referer>"`, or by the URL alone without a referer.
+#[derive(Default)]
+pub struct StubFetcher {
+    pub pages: HashMap<String, String>,
+    pub api: HashMap<String, String>,
+    pub files: HashMap<String, Vec<u8>>,
+    pub requests: Arc<Mutex<Vec<String>>>,
+    client: Client,
+}
+
+impl StubFetcher {
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn answer<T: Clone>(&self, responses: &HashMap<String, T>, key: String) -> anyhow::Result<T> {
+        self.requests.lock().unwrap().push(key.clone());
+
+        responses
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No stub response for {key}"))
+    }
+}
+
+#[async_trait]
+impl Fetcher for StubFetcher {
+    async fn get_page(&self, url: &str) -> anyhow::Result<String> {
+        self.answer(&self.pages, url.to_string())
+    }
+
+    async fn get_api(&self, url: &str, referer: Option<&str>) -> anyhow::Result<String> {
+        let key = match referer {
+            Some(referer) => format!("{url} {referer}"),
+            None => url.to_string(),
+        };
+
+        self.answer(&self.api, key)
+    }
+
+    async fn get_file(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        self.answer(&self.files, url.to_string())
+    }
+
+    fn client(&self) -> &Client {
+        &self.client
+    }
+}
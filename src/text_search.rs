@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use deunicode::deunicode_char;
+
+use crate::{
+    db::{self, Db, SearchField, SearchHit},
+    parser::html_to_text,
+};
+
+/// The number of characters shown around the first match
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Splits a query into phrases, which are either a single word or quoted words
+///
+/// E.g. `red "big ship"` becomes `[["red"], ["big", "ship"]]`.
+fn parse_query(query: &str) -> Vec<Vec<String>> {
+    query
+        .split('"')
+        .enumerate()
+        .flat_map(|(index, part)| {
+            let words = normalize(part);
+
+            // Every odd part is inside of quotes
+            if index % 2 == 1 {
+                vec![words]
+            } else {
+                words.into_iter().map(|word| vec![word]).collect()
+            }
+        })
+        .filter(|phrase| !phrase.is_empty())
+        .collect()
+}
+
+/// Lowercases a character and transliterates it to ASCII, like the `lowercase` and `ascii` filters of the
+/// analyzer of the full-text indexes
+fn fold(c: char) -> String {
+    c.to_lowercase()
+        .map(|c| deunicode_char(c).unwrap_or("[?]"))
+        .collect()
+}
+
+/// Splits a text into lowercase ASCII words, ignoring punctuation
+fn normalize(text: &str) -> Vec<String> {
+    text.chars()
+        .map(fold)
+        .collect::<String>()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether the words of `phrase` appear in `words` next to each other
+fn contains_phrase(words: &[String], phrase: &[String]) -> bool {
+    words.windows(phrase.len()).any(|window| window == phrase)
+}
+
+/// Cuts the text around the first occurrence of `word`
+fn snippet(text: &str, word: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let word: Vec<char> = word.chars().collect();
+
+    // Folding may change the length of characters, so every folded character remembers its original
+    let (folded, origins): (Vec<char>, Vec<usize>) = chars
+        .iter()
+        .enumerate()
+        .flat_map(|(index, c)| {
+            fold(*c)
+                .chars()
+                .map(move |c| (c, index))
+                .collect::<Vec<_>>()
+        })
+        .unzip();
+    let position = folded
+        .windows(word.len().max(1))
+        .position(|window| window == word.as_slice())
+        .map_or(0, |position| origins[position]);
+
+    let start = position.saturating_sub(SNIPPET_CONTEXT);
+    let end = (position + word.len() + SNIPPET_CONTEXT).min(chars.len());
+
+    format!(
+        "{}{}{}",
+        if start > 0 { "..." } else { "" },
+        chars[start..end].iter().collect::<String>(),
+        if end < chars.len() { "..." } else { "" },
+    )
+}
+
+/// A post matching the search, with its best match
+struct RankedPost {
+    title: String,
+    score: f64,
+    field: SearchField,
+    author: Option<String>,
+    snippet: String,
+    best_score: f64,
+}
+
+/// Searches the titles, infobox text, and comments of all stored posts
+///
+/// All words must appear in the same title, infobox, or comment, and quoted phrases must appear as written.
+pub async fn search_local(db: &Db, query: &str, limit: usize) -> anyhow::Result<()> {
+    let phrases = parse_query(query);
+
+    if phrases.is_empty() {
+        anyhow::bail!("The query contains no words");
+    }
+
+    let words = phrases.concat().join(" ");
+    let mut posts: HashMap<String, RankedPost> = HashMap::new();
+
+    for field in SearchField::ALL {
+        for hit in db::search_text(db, field, &words).await? {
+            let SearchHit {
+                post,
+                title,
+                author,
+                text,
+                score,
+            } = hit;
+
+            let text = html_to_text(&text);
+            let text_words = normalize(&text);
+
+            if !phrases
+                .iter()
+                .all(|phrase| contains_phrase(&text_words, phrase))
+            {
+                continue;
+            }
+
+            // BM25 scores are negative for words most documents contain, so they're mapped to (0, 1) first
+            let score = field.weight() / (1. + (-score).exp());
+            let ranked = posts.entry(post.to_string()).or_insert(RankedPost {
+                title: title.unwrap_or_default(),
+                score: 0.,
+                field,
+                author: None,
+                snippet: String::new(),
+                best_score: f64::MIN,
+            });
+
+            ranked.score += score;
+
+            if score > ranked.best_score {
+                ranked.best_score = score;
+                ranked.field = field;
+                ranked.author = author;
+                ranked.snippet = snippet(&text, &phrases[0][0]);
+            }
+        }
+    }
+
+    let mut posts: Vec<_> = posts.into_iter().collect();
+    posts.sort_by(|a, b| b.1.score.total_cmp(&a.1.score).then_with(|| a.0.cmp(&b.0)));
+
+    if posts.is_empty() {
+        println!("Nothing matches {query}.");
+        return Ok(());
+    }
+
+    let count = posts.len();
+    let shown = if limit == 0 { count } else { limit.min(count) };
+
+    for (rank, (id, post)) in posts.into_iter().take(shown).enumerate() {
+        let location = match post.field {
+            SearchField::Title => "title".to_string(),
+            SearchField::Text => "info".to_string(),
+            SearchField::Comment => format!(
+                "comment by {}",
+                post.author.as_deref().unwrap_or("(unknown)")
+            ),
+        };
+
+        println!(
+            "{:>3}. {:>6.2} {} [{id}]\n       {location}: {}",
+            rank + 1,
+            post.score,
+            post.title,
+            post.snippet,
+        );
+    }
+
+    println!("\nShowing {shown} of {count} matching post(s)");
+
+    Ok(())
+}